@@ -1,9 +1,56 @@
+use std::collections::{HashMap, HashSet};
 use std::fmt;
 
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum LedgerKind {
+    Charge,
+    Payment,
+    Interest,
+    Exempt, // free call (e.g. emergency services), recorded at zero cost
+}
+
+#[derive(Clone, Debug)]
+struct LedgerEntry {
+    kind: LedgerKind,
+    amount: f64,
+    operator: Option<usize>, // operator that earned a charge, as it was at charge time
+}
+
+// how amounts are brought to whole cents before they touch a bill's debt
+#[derive(Clone, Copy, Debug, PartialEq, Default)]
+enum RoundingMode {
+    #[default]
+    HalfUp,   // half a cent rounds away from zero
+    Bankers,  // half a cent rounds to the even cent
+    Truncate, // fractions of a cent are dropped
+}
+
+impl RoundingMode {
+    fn round_cents(self, amount: f64) -> f64 {
+        let cents = amount * 100.0;
+        let cents = match self {
+            RoundingMode::HalfUp => cents.round(),
+            RoundingMode::Bankers => cents.round_ties_even(),
+            RoundingMode::Truncate => cents.trunc(),
+        };
+        cents / 100.0
+    }
+}
+
+// how `Bill::merge` combines the two limits
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum MergePolicy {
+    KeepLarger,
+    Sum,
+}
+
 #[derive(Clone)]
 struct Bill {
     limiting_amount: f64,
     current_debt: f64,
+    overdraft_allowance: f64, // how far past the limit a charge may still go
+    ledger: Vec<LedgerEntry>, // every movement on the debt, in order
+    rounding: RoundingMode,
 }
 
 impl Bill {
@@ -11,28 +58,119 @@ impl Bill {
         Self {
             limiting_amount,
             current_debt: 0.0,
+            overdraft_allowance: 0.0,
+            ledger: Vec::new(),
+            rounding: RoundingMode::default(),
         }
     }
 
     fn check(&self, amount: f64) -> bool {
-        (self.current_debt + amount) <= self.limiting_amount + 1e-9
+        (self.current_debt + self.rounding.round_cents(amount)) <= self.limiting_amount + self.overdraft_allowance + 1e-9
+    }
+
+    // true when the charge only fits thanks to the overdraft allowance (so a fee could be applied)
+    fn uses_overdraft(&self, amount: f64) -> bool {
+        (self.current_debt + amount) > self.limiting_amount + 1e-9
+    }
+
+    // adds the charge; returns whether it went into overdraft
+    fn add(&mut self, amount: f64) -> bool {
+        self.record_charge(amount, None)
+    }
+
+    // same as `add`, attributing the revenue to the operator that provided the service
+    fn add_for_operator(&mut self, amount: f64, operator_index: usize) -> bool {
+        self.record_charge(amount, Some(operator_index))
+    }
+
+    // logs an exempt call; never blocked by the limit and never adds debt
+    fn add_exempt(&mut self) {
+        self.ledger.push(LedgerEntry { kind: LedgerKind::Exempt, amount: 0.0, operator: None });
     }
 
-    fn add(&mut self, amount: f64) {
+    fn record_charge(&mut self, amount: f64, operator: Option<usize>) -> bool {
+        let amount = self.rounding.round_cents(amount);
+        let overdraft = self.uses_overdraft(amount);
         self.current_debt += amount;
+        self.ledger.push(LedgerEntry { kind: LedgerKind::Charge, amount, operator });
+        overdraft
     }
 
     fn pay(&mut self, amount: f64) {
+        let amount = self.rounding.round_cents(amount);
+        let paid = amount.min(self.current_debt);
         self.current_debt -= amount;
         if self.current_debt < 0.0 {
             self.current_debt = 0.0;
         }
+        self.ledger.push(LedgerEntry { kind: LedgerKind::Payment, amount: paid, operator: None });
+    }
+
+    // settles the whole outstanding debt; returns what was paid
+    fn pay_full(&mut self) -> f64 {
+        let paid = self.current_debt;
+        self.pay(paid);
+        paid
+    }
+
+    // grows the outstanding debt by `rate` (0.02 = 2%); returns the interest added
+    fn accrue_interest(&mut self, rate: f64) -> f64 {
+        if rate < 0.0 {
+            log::warn!("Ignoring negative interest rate {}", rate);
+            return 0.0;
+        }
+        let interest = self.rounding.round_cents(self.current_debt * rate);
+        if interest > 0.0 {
+            self.current_debt += interest;
+            self.ledger.push(LedgerEntry { kind: LedgerKind::Interest, amount: interest, operator: None });
+        }
+        interest
+    }
+
+    // the ledger as CSV (index, category, amount, running balance); exempt calls leave the balance as is
+    fn ledger_csv(&self) -> String {
+        let mut csv = String::from("index,category,amount,balance\n");
+        let mut balance = 0.0;
+        for (i, e) in self.ledger.iter().enumerate() {
+            let category = match e.kind {
+                LedgerKind::Charge => "charge",
+                LedgerKind::Payment => "payment",
+                LedgerKind::Interest => "interest",
+                LedgerKind::Exempt => "exempt",
+            };
+            match e.kind {
+                LedgerKind::Charge | LedgerKind::Interest => balance += e.amount,
+                LedgerKind::Payment => balance -= e.amount,
+                LedgerKind::Exempt => {}
+            }
+            csv += &format!("{},{},{:.2},{:.2}\n", i, category, e.amount, balance);
+        }
+        csv
+    }
+
+    // takes over `other`'s debt and ledger, leaving it with no debt and an empty ledger
+    fn merge(&mut self, other: &mut Bill, policy: MergePolicy) {
+        self.limiting_amount = match policy {
+            MergePolicy::KeepLarger => self.limiting_amount.max(other.limiting_amount),
+            MergePolicy::Sum => self.limiting_amount + other.limiting_amount,
+        };
+        self.current_debt += other.current_debt;
+        other.current_debt = 0.0;
+        self.ledger.append(&mut other.ledger);
     }
 
     fn change_the_limit(&mut self, amount: f64) {
         self.limiting_amount = amount;
     }
 
+    fn set_overdraft_allowance(&mut self, amount: f64) {
+        self.overdraft_allowance = amount.max(0.0);
+    }
+
+    fn set_rounding(&mut self, mode: RoundingMode) {
+        self.rounding = mode;
+    }
+
     fn get_limiting_amount(&self) -> f64 {
         self.limiting_amount
     }
@@ -45,9 +183,13 @@ impl fmt::Display for Bill {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(
             f,
-            "Bill(limit: {:.2}, debt: {:.2})",
+            "Bill(limit: {:.2}, debt: {:.2}",
             self.limiting_amount, self.current_debt
-        )
+        )?;
+        if self.overdraft_allowance > 0.0 {
+            write!(f, ", overdraft: {:.2}", self.overdraft_allowance)?;
+        }
+        write!(f, ")")
     }
 }
 
@@ -58,6 +200,12 @@ struct Operator {
     message_cost: f64,    // per message
     network_charge: f64,  // per MB
     discount_rate: i32,   // percent (e.g., 10 means 10%)
+    same_operator_discount_rate: i32, // percent off messages to customers on this operator
+    min_charge: f64,      // least a non-empty call costs (connection fee)
+    free_minutes: i32,    // monthly allowance per customer before usage is charged
+    free_messages: i32,
+    free_mb: f64,
+    tag_discounts: HashMap<String, i32>, // percent off calls for customers carrying the tag
 }
 
 impl Operator {
@@ -68,15 +216,42 @@ impl Operator {
             message_cost,
             network_charge,
             discount_rate,
+            same_operator_discount_rate: discount_rate,
+            min_charge: 0.0,
+            free_minutes: 0,
+            free_messages: 0,
+            free_mb: 0.0,
+            tag_discounts: HashMap::new(),
         }
     }
 
+    // the part of a usage that falls beyond what is left of the monthly allowance
+    fn chargeable_minutes(&self, minute: i32, used: i32) -> i32 {
+        (minute - (self.free_minutes - used).max(0)).max(0)
+    }
+    fn chargeable_messages(&self, quantity: i32, used: i32) -> i32 {
+        (quantity - (self.free_messages - used).max(0)).max(0)
+    }
+    fn chargeable_mb(&self, amount: f64, used: f64) -> f64 {
+        (amount - (self.free_mb - used).max(0.0)).max(0.0)
+    }
+
+    // percent off a call; discounts don't stack, the best of the age discount and the customer's tags wins
+    fn talking_discount(&self, customer_age: usize, tags: &HashSet<String>) -> i32 {
+        let age_discount = if customer_age < 18 || customer_age > 65 { self.discount_rate } else { 0 };
+        tags.iter().filter_map(|t| self.tag_discounts.get(t)).copied().fold(age_discount, i32::max)
+    }
+
     fn calculate_talking_cost(&self, minute: i32, customer_age: usize) -> f64 {
+        self.calculate_tagged_talking_cost(minute, customer_age, &HashSet::new())
+    }
+
+    fn calculate_tagged_talking_cost(&self, minute: i32, customer_age: usize, tags: &HashSet<String>) -> f64 {
         let base = self.talking_charge * (minute as f64);
-        let mut cost = base;
-        if customer_age < 18 || customer_age > 65 {
-            let d = (self.discount_rate as f64) / 100.0;
-            cost = base * (1.0 - d);
+        let d = (self.talking_discount(customer_age, tags) as f64) / 100.0;
+        let mut cost = base * (1.0 - d);
+        if minute > 0 {
+            cost = cost.max(self.min_charge);
         }
         cost
     }
@@ -85,7 +260,7 @@ impl Operator {
         let base = self.message_cost * (quantity as f64);
         let mut cost = base;
         if same_operator {
-            let d = (self.discount_rate as f64) / 100.0;
+            let d = (self.same_operator_discount_rate as f64) / 100.0;
             cost = base * (1.0 - d);
         }
         cost
@@ -95,6 +270,18 @@ impl Operator {
         self.network_charge * amount
     }
 
+    // projected bill for a month of usage; `same_operator_fraction` is the share of messages sent within this operator
+    fn estimate_monthly(&self, minutes: i32, messages: i32, mb: f64, customer_age: usize, same_operator_fraction: f64) -> f64 {
+        let fraction = same_operator_fraction.clamp(0.0, 1.0);
+        let minutes = self.chargeable_minutes(minutes, 0);
+        let messages = self.chargeable_messages(messages, 0);
+        let talk = self.calculate_talking_cost(minutes, customer_age);
+        let msg = self.calculate_message_cost(messages, true) * fraction
+            + self.calculate_message_cost(messages, false) * (1.0 - fraction);
+        let net = self.calculate_network_cost(self.chargeable_mb(mb, 0.0));
+        talk + msg + net
+    }
+
     fn get_talking_charge(&self) -> f64 { self.talking_charge }
     fn set_talking_charge(&mut self, v: f64) { self.talking_charge = v; }
     fn get_message_cost(&self) -> f64 { self.message_cost }
@@ -103,6 +290,14 @@ impl Operator {
     fn set_network_charge(&mut self, v: f64) { self.network_charge = v; }
     fn get_discount_rate(&self) -> i32 { self.discount_rate }
     fn set_discount_rate(&mut self, v: i32) { self.discount_rate = v; }
+    fn get_same_operator_discount_rate(&self) -> i32 { self.same_operator_discount_rate }
+    fn set_same_operator_discount_rate(&mut self, v: i32) { self.same_operator_discount_rate = v; }
+    fn get_min_charge(&self) -> f64 { self.min_charge }
+    fn set_min_charge(&mut self, v: f64) { self.min_charge = v; }
+    fn set_free_minutes(&mut self, v: i32) { self.free_minutes = v.max(0); }
+    fn set_free_messages(&mut self, v: i32) { self.free_messages = v.max(0); }
+    fn set_free_mb(&mut self, v: f64) { self.free_mb = v.max(0.0); }
+    fn set_tag_discount(&mut self, tag: &str, v: i32) { self.tag_discounts.insert(tag.to_string(), v); }
 }
 
 impl fmt::Display for Operator {
@@ -115,12 +310,71 @@ impl fmt::Display for Operator {
     }
 }
 
+// named preset of operator rates, e.g. "Student" or "Business"
+#[derive(Clone)]
+struct Plan {
+    name: String,
+    talking_charge: f64,
+    message_cost: f64,
+    network_charge: f64,
+    discount_rate: i32,
+    bill_limit: Option<f64>, // optional cap for bills opened on this plan
+}
+
+impl Plan {
+    fn new(name: &str, talking_charge: f64, message_cost: f64, network_charge: f64, discount_rate: i32, bill_limit: Option<f64>) -> Self {
+        Self {
+            name: name.to_string(),
+            talking_charge,
+            message_cost,
+            network_charge,
+            discount_rate,
+            bill_limit,
+        }
+    }
+
+    fn apply(&self, id: usize) -> Operator {
+        Operator::new(id, self.talking_charge, self.message_cost, self.network_charge, self.discount_rate)
+    }
+}
+
+impl fmt::Display for Plan {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Plan({}, talk: {:.3}/min, msg: {:.3}/msg, net: {:.3}/MB, disc: {}%)",
+            self.name, self.talking_charge, self.message_cost, self.network_charge, self.discount_rate
+        )
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum BillError {
+    OperatorMissing,
+}
+
+impl fmt::Display for BillError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BillError::OperatorMissing => write!(f, "operator missing"),
+        }
+    }
+}
+
+#[derive(Clone)]
 struct Customer {
     id: usize,
     name: String,
     age: usize,
     operator_index: usize, // index into operators array
     bill_index: usize,     // index into bills array
+    bill_indices: Vec<usize>, // every bill the customer owns (charges still go to bill_index)
+    spending_cap: Option<f64>, // monthly limit on charges across all activity, whatever bill they land on
+    period_spend: f64,         // charged so far this month
+    used_minutes: i32,         // usage so far this month, counted against the operator's allowance
+    used_messages: i32,
+    used_mb: f64,
+    tags: HashSet<String>,     // e.g. "student", "veteran"; operators may discount them
 }
 
 impl Customer {
@@ -131,50 +385,129 @@ impl Customer {
             age,
             operator_index,
             bill_index,
+            bill_indices: vec![bill_index],
+            spending_cap: None,
+            period_spend: 0.0,
+            used_minutes: 0,
+            used_messages: 0,
+            used_mb: 0.0,
+            tags: HashSet::new(),
         }
     }
 
-    fn talk(&self, minute: i32, other: &Customer, operators: &Vec<Option<Operator>>, bills: &mut Vec<Option<Bill>>) {
-        // retrieve operator for self
-        let op = operators[self.operator_index].as_ref().expect("Operator missing");
-        let cost = op.calculate_talking_cost(minute, self.age);
+    fn within_spending_cap(&self, cost: f64) -> bool {
+        self.spending_cap.is_none_or(|cap| self.period_spend + cost <= cap + 1e-9)
+    }
+
+    fn operator<'a>(&self, operators: &'a [Option<Operator>]) -> Result<&'a Operator, BillError> {
+        operators.get(self.operator_index).and_then(|op| op.as_ref()).ok_or(BillError::OperatorMissing)
+    }
+
+    // cost of a call with every discount and the remaining free allowance applied, without touching any bill
+    fn quote_talk(&self, minute: i32, operators: &[Option<Operator>]) -> Result<f64, BillError> {
+        let op = self.operator(operators)?;
+        Ok(op.calculate_tagged_talking_cost(op.chargeable_minutes(minute, self.used_minutes), self.age, &self.tags))
+    }
+
+    fn quote_message(&self, quantity: i32, other: &Customer, operators: &[Option<Operator>]) -> Result<f64, BillError> {
+        let same_operator = self.operator_index == other.operator_index;
+        let op = self.operator(operators)?;
+        Ok(op.calculate_message_cost(op.chargeable_messages(quantity, self.used_messages), same_operator))
+    }
+
+    fn quote_connection(&self, amount: f64, operators: &[Option<Operator>]) -> Result<f64, BillError> {
+        let op = self.operator(operators)?;
+        Ok(op.calculate_network_cost(op.chargeable_mb(amount, self.used_mb)))
+    }
+
+    fn talk(&mut self, minute: i32, other: &Customer, operators: &Vec<Option<Operator>>, bills: &mut Vec<Option<Bill>>) {
+        let cost = match self.quote_talk(minute, operators) {
+            Ok(cost) => cost,
+            Err(e) => {
+                log::warn!("{} cannot talk: {}", self.name, e);
+                return;
+            }
+        };
+
+        if !self.within_spending_cap(cost) {
+            log::warn!("{} wanted to talk for {} min (cost {:.2}) but the spending cap is reached. No action taken.", self.name, minute, cost);
+            return;
+        }
 
         // check bill
         let bill = bills[self.bill_index].as_mut().expect("Bill missing");
         if bill.check(cost) {
-            bill.add(cost);
-            println!("{} talked to {} for {} min. Cost {:.2} added to bill {}.", self.name, other.name, minute, cost, self.bill_index);
+            if bill.add_for_operator(cost, self.operator_index) {
+                log::warn!("{}'s call went into overdraft on bill {}.", self.name, self.bill_index);
+            }
+            self.period_spend += cost;
+            self.used_minutes += minute;
+            log::info!("{} talked to {} for {} min. Cost {:.2} added to bill {}.", self.name, other.name, minute, cost, self.bill_index);
         } else {
-            println!("{} wanted to talk for {} min (cost {:.2}) but limit exceeded. No action taken.", self.name, minute, cost);
+            log::warn!("{} wanted to talk for {} min (cost {:.2}) but limit exceeded. No action taken.", self.name, minute, cost);
         }
     }
 
+    // calls to exempt numbers (emergency services) skip pricing and the bill limit entirely
+    fn talk_exempt(&self, minute: i32, other: &Customer, bills: &mut [Option<Bill>]) {
+        let bill = bills[self.bill_index].as_mut().expect("Bill missing");
+        bill.add_exempt();
+        log::info!("{} made an exempt call to {} for {} min. No charge.", self.name, other.name, minute);
+    }
+
     // void message(int quantity, Customer other)
-    fn message(&self, quantity: i32, other: &Customer, operators: &Vec<Option<Operator>>, bills: &mut Vec<Option<Bill>>) {
-        let op_self = operators[self.operator_index].as_ref().expect("Operator missing");
-        let same_operator = self.operator_index == other.operator_index;
-        let cost = op_self.calculate_message_cost(quantity, same_operator);
+    fn message(&mut self, quantity: i32, other: &Customer, operators: &Vec<Option<Operator>>, bills: &mut Vec<Option<Bill>>) {
+        let cost = match self.quote_message(quantity, other, operators) {
+            Ok(cost) => cost,
+            Err(e) => {
+                log::warn!("{} cannot send messages: {}", self.name, e);
+                return;
+            }
+        };
+
+        if !self.within_spending_cap(cost) {
+            log::warn!("{} wanted to send {} messages (cost {:.2}) but the spending cap is reached. No action taken.", self.name, quantity, cost);
+            return;
+        }
 
         let bill = bills[self.bill_index].as_mut().expect("Bill missing");
         if bill.check(cost) {
-            bill.add(cost);
-            println!("{} sent {} messages to {}. Cost {:.2} added to bill {}.", self.name, quantity, other.name, cost, self.bill_index);
+            if bill.add_for_operator(cost, self.operator_index) {
+                log::warn!("{}'s messages went into overdraft on bill {}.", self.name, self.bill_index);
+            }
+            self.period_spend += cost;
+            self.used_messages += quantity;
+            log::info!("{} sent {} messages to {}. Cost {:.2} added to bill {}.", self.name, quantity, other.name, cost, self.bill_index);
         } else {
-            println!("{} wanted to send {} messages (cost {:.2}) but limit exceeded. No action taken.", self.name, quantity, cost);
+            log::warn!("{} wanted to send {} messages (cost {:.2}) but limit exceeded. No action taken.", self.name, quantity, cost);
         }
     }
 
     // void connection(double amount) // amount = MB
-    fn connection(&self, amount: f64, operators: &Vec<Option<Operator>>, bills: &mut Vec<Option<Bill>>) {
-        let op = operators[self.operator_index].as_ref().expect("Operator missing");
-        let cost = op.calculate_network_cost(amount);
+    fn connection(&mut self, amount: f64, operators: &Vec<Option<Operator>>, bills: &mut Vec<Option<Bill>>) {
+        let cost = match self.quote_connection(amount, operators) {
+            Ok(cost) => cost,
+            Err(e) => {
+                log::warn!("{} cannot connect: {}", self.name, e);
+                return;
+            }
+        };
+
+        if !self.within_spending_cap(cost) {
+            log::warn!("{} wanted to use {:.2} MB (cost {:.2}) but the spending cap is reached. No action taken.", self.name, amount, cost);
+            return;
+        }
 
         let bill = bills[self.bill_index].as_mut().expect("Bill missing");
         if bill.check(cost) {
-            bill.add(cost);
-            println!("{} used {:.2} MB. Cost {:.2} added to bill {}.", self.name, amount, cost, self.bill_index);
+            if bill.add_for_operator(cost, self.operator_index) {
+                log::warn!("{}'s connection went into overdraft on bill {}.", self.name, self.bill_index);
+            }
+            self.period_spend += cost;
+            self.used_mb += amount;
+            log::info!("{} used {:.2} MB. Cost {:.2} added to bill {}.", self.name, amount, cost, self.bill_index);
         } else {
-            println!("{} wanted to use {:.2} MB (cost {:.2}) but limit exceeded. No action taken.", self.name, amount, cost);
+            log::warn!("{} wanted to use {:.2} MB (cost {:.2}) but limit exceeded. No action taken.", self.name, amount, cost);
         }
     }
 
@@ -184,8 +517,42 @@ impl Customer {
     fn get_operator_index(&self) -> usize { self.operator_index }
     fn set_operator_index(&mut self, v: usize) { self.operator_index = v; }
 
+    fn add_tag(&mut self, tag: &str) { self.tags.insert(tag.to_string()); }
+
+    fn get_spending_cap(&self) -> Option<f64> { self.spending_cap }
+    fn set_spending_cap(&mut self, v: Option<f64>) { self.spending_cap = v; }
+
     fn get_bill_index(&self) -> usize { self.bill_index }
-    fn set_bill_index(&mut self, v: usize) { self.bill_index = v; }
+    fn set_bill_index(&mut self, v: usize) {
+        self.bill_index = v;
+        self.add_bill(v);
+    }
+
+    fn add_bill(&mut self, bill_index: usize) {
+        if !self.bill_indices.contains(&bill_index) {
+            self.bill_indices.push(bill_index);
+        }
+    }
+
+    // pays towards every owned bill, highest debt first; returns whatever is left over
+    fn pay_across(&self, amount: f64, bills: &mut [Option<Bill>]) -> f64 {
+        let mut order = self.bill_indices.clone();
+        order.sort_by(|a, b| {
+            let debt = |i: &usize| bills[*i].as_ref().map_or(0.0, |b| b.get_current_debt());
+            debt(b).total_cmp(&debt(a))
+        });
+        let mut remaining = amount;
+        for i in order {
+            if remaining <= 0.0 {
+                break;
+            }
+            let bill = bills[i].as_mut().expect("Bill missing");
+            let portion = remaining.min(bill.get_current_debt());
+            bill.pay(portion);
+            remaining -= portion;
+        }
+        remaining
+    }
 }
 
 impl fmt::Display for Customer {
@@ -225,6 +592,149 @@ fn create_customers() -> Vec<Option<Customer>> {
     ]
 }
 
+// index of the cheapest operator for the usage profile, plus every operator's estimate; None with no operators.
+// messages are priced as cross-operator since the customer isn't on any of them yet.
+fn cheaper_operator(ops: &[Operator], minutes: i32, messages: i32, mb: f64, age: usize) -> Option<(usize, Vec<f64>)> {
+    if ops.is_empty() {
+        return None;
+    }
+    let costs: Vec<f64> = ops.iter().map(|op| op.estimate_monthly(minutes, messages, mb, age, 0.0)).collect();
+    let mut best = 0;
+    for (i, &c) in costs.iter().enumerate() {
+        if c < costs[best] {
+            best = i;
+        }
+    }
+    Some((best, costs))
+}
+
+fn total_debt_for(customer: &Customer, bills: &[Option<Bill>]) -> f64 {
+    customer
+        .bill_indices
+        .iter()
+        .filter_map(|&i| bills[i].as_ref())
+        .map(|b| b.get_current_debt())
+        .sum()
+}
+
+// ids of the customers currently on operator `op_index`
+fn customers_on_operator(customers: &[Option<Customer>], op_index: usize) -> Vec<usize> {
+    customers
+        .iter()
+        .flatten()
+        .filter(|c| c.operator_index == op_index)
+        .map(|c| c.id)
+        .collect()
+}
+
+// money across all bills; charged (charges plus interest) always equals paid plus outstanding
+struct FinancialSummary {
+    charged: f64,
+    paid: f64,
+    outstanding: f64,
+    operator_revenue: f64,        // the part of `charged` attributed to an operator
+    collection_rate: Option<f64>, // paid / charged; None before anything was charged
+}
+
+fn financial_summary(bills: &[Option<Bill>], revenue: &HashMap<usize, f64>) -> FinancialSummary {
+    let mut charged = 0.0;
+    let mut paid = 0.0;
+    for e in bills.iter().flatten().flat_map(|b| b.ledger.iter()) {
+        match e.kind {
+            LedgerKind::Charge | LedgerKind::Interest => charged += e.amount,
+            LedgerKind::Payment => paid += e.amount,
+            LedgerKind::Exempt => {}
+        }
+    }
+    FinancialSummary {
+        charged,
+        paid,
+        outstanding: bills.iter().flatten().map(|b| b.get_current_debt()).sum(),
+        operator_revenue: revenue.values().sum(),
+        collection_rate: if charged > 0.0 { Some(paid / charged) } else { None },
+    }
+}
+
+impl fmt::Display for FinancialSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "charged: {:.2} (operators: {:.2}), paid: {:.2}, outstanding: {:.2}",
+            self.charged, self.operator_revenue, self.paid, self.outstanding
+        )?;
+        if let Some(rate) = self.collection_rate {
+            write!(f, ", collected: {:.1}%", rate * 100.0)?;
+        }
+        Ok(())
+    }
+}
+
+// revenue earned by each operator index, from the charges recorded in every bill's ledger
+fn operator_revenue(bills: &[Option<Bill>]) -> HashMap<usize, f64> {
+    let mut revenue = HashMap::new();
+    for e in bills.iter().flatten().flat_map(|b| b.ledger.iter()) {
+        if let (LedgerKind::Charge, Some(op)) = (e.kind, e.operator) {
+            *revenue.entry(op).or_insert(0.0) += e.amount;
+        }
+    }
+    revenue
+}
+
+// month end: unpaid balances on every bill accrue interest once and spending caps start over
+fn close_month(customers: &mut [Option<Customer>], bills: &mut [Option<Bill>], rate: f64) {
+    for c in customers.iter_mut().flatten() {
+        c.period_spend = 0.0;
+        c.used_minutes = 0;
+        c.used_messages = 0;
+        c.used_mb = 0.0;
+    }
+    for (i, b) in bills.iter_mut().enumerate() {
+        if let Some(b) = b {
+            let interest = b.accrue_interest(rate);
+            if interest > 0.0 {
+                log::info!("bill[{}] accrued {:.2} interest", i, interest);
+            }
+        }
+    }
+}
+
+// ages every customer by `years`, which can move them into or out of the age discount
+fn advance_time(customers: &mut [Option<Customer>], years: usize) {
+    for c in customers.iter_mut().flatten() {
+        c.age += years;
+    }
+}
+
+fn create_plans() -> Vec<Plan> {
+    vec![
+        Plan::new("Student", 0.3, 0.05, 0.01, 20, Some(40.0)),
+        Plan::new("Business", 0.6, 0.1, 0.005, 0, None),
+    ]
+}
+
+fn find_plan<'a>(plans: &'a [Plan], name: &str) -> Option<&'a Plan> {
+    plans.iter().find(|p| p.name == name)
+}
+
+// registers the customer on the plan's operator (opened on the plan's first customer, then shared via
+// `plan_operators`) with a bill of their own; returns the customer index
+fn join_plan(plan: &Plan, name: &str, age: usize, customers: &mut Vec<Option<Customer>>, operators: &mut Vec<Option<Operator>>, bills: &mut Vec<Option<Bill>>, plan_operators: &mut HashMap<String, usize>) -> usize {
+    let op_index = match plan_operators.get(&plan.name) {
+        Some(&i) if operators.get(i).is_some_and(|op| op.is_some()) => i,
+        _ => {
+            let i = operators.len();
+            operators.push(Some(plan.apply(i)));
+            plan_operators.insert(plan.name.clone(), i);
+            i
+        }
+    };
+    let bill_index = bills.len();
+    bills.push(Some(Bill::new(plan.bill_limit.unwrap_or(100.0))));
+    let id = customers.len();
+    customers.push(Some(Customer::new(id, name, age, op_index, bill_index)));
+    id
+}
+
 fn print_state(customers: &Vec<Option<Customer>>, operators: &Vec<Option<Operator>>, bills: &Vec<Option<Bill>>) {
     println!("=== Operators ===");
     for (i, op) in operators.iter().enumerate() {
@@ -252,10 +762,21 @@ fn print_state(customers: &Vec<Option<Customer>>, operators: &Vec<Option<Operato
             println!("cust[{}] = None", i);
         }
     }
+
+    println!("\n=== Revenue ===");
+    let revenue = operator_revenue(bills);
+    let mut op_ids: Vec<_> = revenue.keys().cloned().collect();
+    op_ids.sort_unstable();
+    for i in op_ids {
+        println!("op[{}] earned {:.2}", i, revenue[&i]);
+    }
     println!("=================\n");
 }
 
 fn main() {
+    // demo narration goes through the logger (RUST_LOG controls it); state dumps stay on stdout
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info")).init();
+
     let mut operators: Vec<Option<Operator>> = Vec::new();
     let mut bills: Vec<Option<Bill>> = Vec::new();
     let mut customers: Vec<Option<Customer>> = Vec::new();
@@ -264,25 +785,126 @@ fn main() {
     bills = create_bill_list();
     customers = create_customers();
 
+    // Which operator is cheaper for a talk-heavy vs a message-heavy profile
+    {
+        let ops: Vec<Operator> = operators.iter().flatten().cloned().collect();
+        for (label, minutes, messages) in [("talk-heavy", 300, 10), ("message-heavy", 10, 500)] {
+            match cheaper_operator(&ops, minutes, messages, 100.0, 30) {
+                Some((best, costs)) => println!("Cheapest operator for {} usage: op[{}] (costs: {:.2?})", label, best, costs),
+                None => println!("No operators to compare for {} usage", label),
+            }
+        }
+    }
+
+    // Customers on named plans
+    let plans = create_plans();
+    let mut plan_operators = HashMap::new();
+    for (name, age, plan_name) in [("Dave", 20, "Student"), ("Erin", 45, "Business")] {
+        let plan = find_plan(&plans, plan_name).expect("Plan missing");
+        log::info!("{} joins {}", name, plan);
+        join_plan(plan, name, age, &mut customers, &mut operators, &mut bills, &mut plan_operators);
+    }
+
+    // Operator 1 starts billing at least 1.00 per call: a short call pays the minimum, a long one its rate
+    {
+        let op = operators[1].as_mut().unwrap();
+        op.set_min_charge(1.0);
+        for minutes in [1, 10] {
+            println!("op[1] {} min call: {:.2} (minimum {:.2})", minutes, op.calculate_talking_cost(minutes, 30), op.get_min_charge());
+        }
+    }
+
+    // Operator 1 gives veterans 20% off calls: Bob quotes a 10 min call without and with the tag
+    {
+        operators[1].as_mut().unwrap().set_tag_discount("veteran", 20);
+        let mut veteran = customers[1].as_ref().unwrap().clone();
+        let plain = veteran.quote_talk(10, &operators);
+        veteran.add_tag("veteran");
+        println!("10 min call for {}: {:.2?} untagged, {:.2?} as a veteran", veteran.name, plain, veteran.quote_talk(10, &operators));
+    }
+
     println!("Initial State:");
     print_state(&customers, &operators, &bills);
 
+    // Monthly estimate for Alice: 120 min, 40 messages (half to her own operator), 500 MB
+    {
+        let alice = customers[0].as_ref().unwrap();
+        let op = operators[alice.get_operator_index()].as_ref().unwrap();
+        println!("Estimated monthly cost for {}: {:.2}", alice.name, op.estimate_monthly(120, 40, 500.0, alice.get_age(), 0.5));
+    }
+
     {
-        let alice = customers[0].as_ref().unwrap().clone();
         let bob = customers[1].as_ref().unwrap().clone();
+        let alice = customers[0].as_mut().unwrap();
         alice.talk(10, &bob, &operators, &mut bills);
     }
 
     {
-        let bob = customers[1].as_ref().unwrap().clone();
         let alice = customers[0].as_ref().unwrap().clone();
+        let bob = customers[1].as_mut().unwrap();
         bob.message(5, &alice, &operators, &mut bills);
     }
 
+    // Operator 0 rewards on-net messaging beyond its age discount; Carol then messages Alice on the same network
+    {
+        let op = operators[0].as_mut().unwrap();
+        op.set_same_operator_discount_rate(op.get_same_operator_discount_rate() + 15);
+        println!("op[0] same-operator message discount: {}%", op.get_same_operator_discount_rate());
+        let alice = customers[0].as_ref().unwrap().clone();
+        let carol = customers[2].as_mut().unwrap();
+        carol.message(5, &alice, &operators, &mut bills);
+    }
+
+    // Bob caps his monthly spending at 1.00: a 100 MB connection is blocked although his bill has room
+    {
+        let bob = customers[1].as_mut().unwrap();
+        bob.set_spending_cap(Some(1.0));
+        bob.connection(100.0, &operators, &mut bills);
+        log::info!("Bob's cap: {:?}, bill[{}] debt: {:.2}", bob.get_spending_cap(), bob.get_bill_index(), bills[1].as_ref().unwrap().get_current_debt());
+    }
+
+    // Erin's Business operator bundles 100 free minutes, 50 messages and 500 MB a month:
+    // an 80 min call is free, the next 40 min call bills only 20 of them
+    {
+        let erin_index = customers.iter().position(|c| c.as_ref().is_some_and(|c| c.name == "Erin")).unwrap();
+        let bob = customers[1].as_ref().unwrap().clone();
+        let erin = customers[erin_index].as_mut().unwrap();
+        let op = operators[erin.get_operator_index()].as_mut().unwrap();
+        op.set_free_minutes(100);
+        op.set_free_messages(50);
+        op.set_free_mb(500.0);
+        for minutes in [80, 40] {
+            erin.talk(minutes, &bob, &operators, &mut bills);
+        }
+    }
+
+    // Carol's bill allows a 5.00 overdraft: 3000 MB fills her 30.00 limit, her next connection dips into it
+    {
+        bills[2].as_mut().unwrap().set_overdraft_allowance(5.0);
+        let carol = customers[2].as_mut().unwrap();
+        carol.connection(3000.0, &operators, &mut bills);
+    }
+
+    // Carol is at her limit but can still reach emergency services (Bob stands in for the hotline)
+    {
+        let carol = customers[2].as_ref().unwrap();
+        let bob = customers[1].as_ref().unwrap();
+        carol.talk_exempt(5, bob, &mut bills);
+    }
+
+    // Quote before committing: Carol previews a 200 MB connection
+    {
+        let carol = customers[2].as_ref().unwrap();
+        match carol.quote_connection(200.0, &operators) {
+            Ok(cost) => println!("Quote for {} using 200.00 MB: {:.2}", carol.name, cost),
+            Err(e) => println!("No quote for {}: {}", carol.name, e),
+        }
+    }
+
     // 5. A customer can connect to the internet;
     // Carol (2) uses 200 MB
     {
-        let carol = customers[2].as_ref().unwrap().clone();
+        let carol = customers[2].as_mut().unwrap();
         carol.connection(200.0, &operators, &mut bills);
     }
 
@@ -290,16 +912,61 @@ fn main() {
     // Pay from Bill 0: pay 3.0
     {
         let b = bills[0].as_mut().unwrap();
-        println!("Paying 3.0 towards bill[0]. Old debt: {:.2}", b.get_current_debt());
+        log::info!("Paying 3.0 towards bill[0]. Old debt: {:.2}", b.get_current_debt());
         b.pay(3.0);
-        println!("New debt: {:.2}", b.get_current_debt());
+        log::info!("New debt: {:.2}", b.get_current_debt());
+    }
+
+    // Bob opens a work bill and pays 10.0 across both of his bills
+    {
+        bills.push(Some(Bill::new(80.0)));
+        let work_bill = bills.len() - 1;
+        bills[work_bill].as_mut().unwrap().add(7.5);
+        let bob = customers[1].as_mut().unwrap();
+        bob.add_bill(work_bill);
+        log::info!("Bob owes {:.2} across his bills", total_debt_for(bob, &bills));
+        let left = bob.pay_across(10.0, &mut bills);
+        log::info!("Bob paid 10.00 across his bills ({:.2} unused). Now owes {:.2}", left, total_debt_for(bob, &bills));
+    }
+
+    // Bob clears the rest of his work bill in one go
+    {
+        let work_bill = bills.len() - 1;
+        let b = bills[work_bill].as_mut().unwrap();
+        b.add(4.0);
+        let paid = b.pay_full();
+        log::info!("Paid bill[{}] in full: {:.2}. Now owes {:.2}", work_bill, paid, b.get_current_debt());
+    }
+
+    // A 0.125 charge stored under each rounding mode
+    for mode in [RoundingMode::HalfUp, RoundingMode::Bankers, RoundingMode::Truncate] {
+        let mut b = Bill::new(10.0);
+        b.set_rounding(mode);
+        b.add(0.125);
+        println!("0.125 charged with {:?} rounding: debt {}", mode, b.get_current_debt());
+    }
+
+    // Bob runs up 6.00 on the work bill again and folds it into his main bill
+    {
+        let work_bill = bills.len() - 1;
+        let mut work = bills[work_bill].take().unwrap();
+        work.add(6.0);
+        for policy in [MergePolicy::KeepLarger, MergePolicy::Sum] {
+            let mut preview = bills[1].clone().unwrap();
+            preview.merge(&mut work.clone(), policy);
+            log::info!("Merging with {:?} would give {}", policy, preview);
+        }
+        let main_bill = bills[1].as_mut().unwrap();
+        main_bill.merge(&mut work, MergePolicy::KeepLarger);
+        log::info!("Merged bill[{}] into bill[1]: {}. Left behind: {}", work_bill, main_bill, work);
+        bills[work_bill] = Some(work);
     }
 
     // 7. A customer can change his/her operator;
     // Bob (1) switches to operator 0
     {
         let cust_mut = customers[1].as_mut().unwrap();
-        println!("Bob switching from operator {} to operator 0", cust_mut.get_operator_index());
+        log::info!("Bob switching from operator {} to operator 0", cust_mut.get_operator_index());
         cust_mut.set_operator_index(0);
     }
 
@@ -307,23 +974,401 @@ fn main() {
     // Increase Bob's bill limit (bill index 1) to 200.0
     {
         let b = bills[1].as_mut().unwrap();
-        println!("Changing bill[1] limit from {:.2} to 200.00", b.get_limiting_amount());
+        log::info!("Changing bill[1] limit from {:.2} to 200.00", b.get_limiting_amount());
         b.change_the_limit(200.0);
     }
 
+    for i in 0..operators.len() {
+        println!("op[{}] customers: {:?}", i, customers_on_operator(&customers, i));
+    }
+
+    // Close the month at 2% interest on whatever is still owed
+    close_month(&mut customers, &mut bills, 0.02);
+    print!("bill[2] ledger:\n{}", bills[2].as_ref().unwrap().ledger_csv());
+    println!("Finances: {}", financial_summary(&bills, &operator_revenue(&bills)));
+
     println!("\nState after operations:");
     print_state(&customers, &operators, &bills);
 
     // Additional demonstration: attempt an action that exceeds the limit
     // Try to have Alice (bill 0) consume a large connection that would exceed her limit
     {
-        let alice = customers[0].as_ref().unwrap().clone();
-        println!("Attempting a large connection for Alice that should exceed limit:");
+        let alice = customers[0].as_mut().unwrap();
+        log::info!("Attempting a large connection for Alice that should exceed limit:");
         alice.connection(10000.0, &operators, &mut bills);
     }
 
+    // A year passes: Alice turns 18 and her calls lose the age discount
+    {
+        let before = customers[0].as_ref().unwrap().quote_talk(10, &operators);
+        advance_time(&mut customers, 1);
+        let alice = customers[0].as_ref().unwrap();
+        log::info!("{} is now {}; a 10 min call went from {:.2?} to {:.2?}", alice.name, alice.get_age(), before, alice.quote_talk(10, &operators));
+    }
+
     println!("\nFinal state:");
     print_state(&customers, &operators, &bills);
 
     println!("Demo complete.");
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn monthly_estimate_matches_the_charges_it_predicts() {
+        let operators = create_operator_list();
+        let mut bills = vec![Some(Bill::new(1000.0))];
+        let mut alice = Customer::new(0, "Alice", 17, 0, 0);
+        let same = Customer::new(1, "Same", 30, 0, 0);
+        let other = Customer::new(2, "Other", 30, 1, 0);
+
+        alice.talk(120, &same, &operators, &mut bills);
+        alice.message(20, &same, &operators, &mut bills);
+        alice.message(20, &other, &operators, &mut bills);
+        alice.connection(500.0, &operators, &mut bills);
+
+        let estimate = operators[0].as_ref().unwrap().estimate_monthly(120, 40, 500.0, 17, 0.5);
+        let charged = bills[0].as_ref().unwrap().get_current_debt();
+        assert!((estimate - charged).abs() < 1e-9, "estimate {} vs charged {}", estimate, charged);
+    }
+
+    #[test]
+    fn customers_on_the_same_plan_share_its_operator() {
+        let plans = create_plans();
+        let (mut customers, mut operators, mut bills) = (Vec::new(), Vec::new(), Vec::new());
+        let mut plan_operators = HashMap::new();
+        let student = find_plan(&plans, "Student").unwrap();
+        let business = find_plan(&plans, "Business").unwrap();
+        let a = join_plan(student, "A", 20, &mut customers, &mut operators, &mut bills, &mut plan_operators);
+        let b = join_plan(student, "B", 21, &mut customers, &mut operators, &mut bills, &mut plan_operators);
+        let c = join_plan(business, "C", 40, &mut customers, &mut operators, &mut bills, &mut plan_operators);
+
+        assert_eq!(operators.len(), 2);
+        let op = |i: usize| customers[i].as_ref().map(|c: &Customer| c.operator_index).unwrap();
+        assert_eq!(op(a), op(b));
+        assert_ne!(op(a), op(c));
+        assert_eq!(bills.len(), 3, "every customer still gets a bill of their own");
+        assert_eq!(customers_on_operator(&customers, op(a)), vec![a, b]);
+    }
+
+    #[test]
+    fn cheaper_operator_follows_the_usage_profile() {
+        let ops: Vec<Operator> = create_operator_list().into_iter().flatten().collect();
+        let (talk_best, talk_costs) = cheaper_operator(&ops, 300, 10, 100.0, 30).unwrap();
+        let (msg_best, msg_costs) = cheaper_operator(&ops, 10, 500, 100.0, 30).unwrap();
+        assert_eq!(talk_best, 0, "costs: {:?}", talk_costs);
+        assert_eq!(msg_best, 1, "costs: {:?}", msg_costs);
+        assert!(cheaper_operator(&[], 300, 10, 100.0, 30).is_none());
+    }
+
+    #[test]
+    fn payment_spills_from_a_cleared_bill_onto_the_next() {
+        let mut bills = vec![Some(Bill::new(50.0)), Some(Bill::new(50.0))];
+        bills[0].as_mut().unwrap().add(3.0);
+        bills[1].as_mut().unwrap().add(5.0);
+        let mut bob = Customer::new(0, "Bob", 30, 0, 0);
+        bob.add_bill(1);
+        assert_eq!(total_debt_for(&bob, &bills), 8.0);
+
+        // the larger debt (bill 1) is cleared first, the rest goes to bill 0
+        assert_eq!(bob.pay_across(6.0, &mut bills), 0.0);
+        assert_eq!(bills[1].as_ref().unwrap().get_current_debt(), 0.0);
+        assert_eq!(bills[0].as_ref().unwrap().get_current_debt(), 2.0);
+
+        assert_eq!(bob.pay_across(5.0, &mut bills), 3.0);
+        assert_eq!(total_debt_for(&bob, &bills), 0.0);
+    }
+
+    #[test]
+    fn quotes_match_the_charges_that_follow() {
+        let mut operators = create_operator_list();
+        {
+            let op = operators[0].as_mut().unwrap();
+            op.set_free_minutes(5);
+            op.set_tag_discount("veteran", 30);
+            // the one-minute call falls under the minimum and must be quoted at it
+            op.set_min_charge(0.75);
+        }
+        let mut bills = vec![Some(Bill::new(1000.0))];
+        let mut carol = Customer::new(0, "Carol", 70, 0, 0);
+        carol.add_tag("veteran");
+        let same = Customer::new(1, "Same", 30, 0, 0);
+        let other = Customer::new(2, "Other", 30, 1, 0);
+        let debt = |bills: &Vec<Option<Bill>>| bills[0].as_ref().unwrap().get_current_debt();
+
+        // the second call starts inside the free allowance left by the first
+        for minutes in [3, 7, 1] {
+            let quote = carol.quote_talk(minutes, &operators).unwrap();
+            let before = debt(&bills);
+            carol.talk(minutes, &same, &operators, &mut bills);
+            assert!((debt(&bills) - before - quote).abs() < 1e-9, "{} min: quote {}", minutes, quote);
+        }
+        for peer in [&same, &other] {
+            let quote = carol.quote_message(12, peer, &operators).unwrap();
+            let before = debt(&bills);
+            carol.message(12, peer, &operators, &mut bills);
+            assert!((debt(&bills) - before - quote).abs() < 1e-9);
+        }
+        let quote = carol.quote_connection(250.0, &operators).unwrap();
+        let before = debt(&bills);
+        carol.connection(250.0, &operators, &mut bills);
+        assert!((debt(&bills) - before - quote).abs() < 1e-9);
+
+        carol.set_operator_index(7);
+        assert_eq!(carol.quote_talk(1, &operators), Err(BillError::OperatorMissing));
+    }
+
+    #[test]
+    fn charge_past_the_limit_fits_the_overdraft_and_is_flagged() {
+        let mut bill = Bill::new(30.0);
+        assert!(!bill.add(25.0));
+        assert!(!bill.check(8.0), "no allowance: the limit is strict");
+        bill.set_overdraft_allowance(5.0);
+        assert!(bill.check(8.0));
+        assert!(bill.uses_overdraft(8.0));
+        assert!(bill.add(8.0), "the charge is flagged as overdraft");
+        assert_eq!(bill.get_current_debt(), 33.0);
+        assert!(!bill.check(2.5), "the allowance is a hard ceiling too");
+    }
+
+    #[test]
+    fn month_close_adds_interest_once() {
+        let mut bills = vec![Some(Bill::new(100.0)), Some(Bill::new(100.0)), Some(Bill::new(100.0))];
+        bills[0].as_mut().unwrap().add(50.0);
+        close_month(&mut [], &mut bills, 0.02);
+
+        let bill = bills[0].as_ref().unwrap();
+        assert_eq!(bill.get_current_debt(), 51.0);
+        let interest: Vec<f64> = bill.ledger.iter().filter(|e| e.kind == LedgerKind::Interest).map(|e| e.amount).collect();
+        assert_eq!(interest, vec![1.0]);
+        assert!(bills[1].as_ref().unwrap().ledger.is_empty(), "no debt, no interest entry");
+
+        bills[0].as_mut().unwrap().accrue_interest(-0.5);
+        assert_eq!(bills[0].as_ref().unwrap().get_current_debt(), 51.0);
+    }
+
+    #[test]
+    fn customers_group_by_operator() {
+        let customers = create_customers();
+        assert_eq!(customers_on_operator(&customers, 0), vec![0, 2]);
+        assert_eq!(customers_on_operator(&customers, 1), vec![1]);
+        assert!(customers_on_operator(&customers, 2).is_empty());
+    }
+
+    #[test]
+    fn revenue_follows_the_operator_at_charge_time() {
+        let operators = create_operator_list();
+        let mut bills = vec![Some(Bill::new(1000.0))];
+        let mut alice = Customer::new(0, "Alice", 30, 0, 0);
+        let bob = Customer::new(1, "Bob", 30, 1, 0);
+        let first = alice.quote_talk(10, &operators).unwrap();
+        alice.talk(10, &bob, &operators, &mut bills);
+        // switching mid-run only moves the charges that come after
+        alice.set_operator_index(1);
+        let second = alice.quote_talk(10, &operators).unwrap();
+        alice.talk(10, &bob, &operators, &mut bills);
+        bills[0].as_mut().unwrap().add(4.0);
+
+        let revenue = operator_revenue(&bills);
+        assert_eq!(revenue.len(), 2, "a charge without an operator earns nobody anything");
+        assert!((revenue[&0] - first).abs() < 1e-9);
+        assert!((revenue[&1] - second).abs() < 1e-9);
+    }
+
+    #[test]
+    fn same_operator_rate_is_separate_from_the_age_discount() {
+        let mut op = Operator::new(0, 0.5, 0.1, 0.01, 10);
+        assert_eq!(op.get_same_operator_discount_rate(), 10, "defaults to the general rate");
+        op.set_same_operator_discount_rate(50);
+        // a 17-year-old gets the age discount on calls only
+        assert!((op.calculate_talking_cost(10, 17) - 4.5).abs() < 1e-9);
+        assert!((op.calculate_message_cost(10, true) - 0.5).abs() < 1e-9);
+        assert!((op.calculate_message_cost(10, false) - 1.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn exempt_call_goes_through_at_the_limit() {
+        let operators = create_operator_list();
+        let mut bills = vec![Some(Bill::new(5.0))];
+        let mut alice = Customer::new(0, "Alice", 30, 0, 0);
+        let hotline = Customer::new(1, "Hotline", 30, 1, 0);
+        alice.talk(10, &hotline, &operators, &mut bills);
+        assert_eq!(bills[0].as_ref().unwrap().get_current_debt(), 5.0);
+        assert!(!bills[0].as_ref().unwrap().check(0.5));
+
+        alice.talk_exempt(30, &hotline, &mut bills);
+        let bill = bills[0].as_ref().unwrap();
+        assert_eq!(bill.get_current_debt(), 5.0);
+        let last = bill.ledger.last().unwrap();
+        assert_eq!((last.kind, last.amount), (LedgerKind::Exempt, 0.0));
+    }
+
+    #[test]
+    fn pay_full_clears_the_debt_and_logs_the_payment() {
+        let mut bill = Bill::new(100.0);
+        bill.add(12.5);
+        bill.add(7.25);
+        assert_eq!(bill.pay_full(), 19.75);
+        assert_eq!(bill.get_current_debt(), 0.0);
+        let last = bill.ledger.last().unwrap();
+        assert_eq!((last.kind, last.amount), (LedgerKind::Payment, 19.75));
+    }
+
+    #[test]
+    fn short_calls_pay_the_minimum_charge() {
+        let mut op = Operator::new(1, 0.7, 0.08, 0.015, 5);
+        op.set_min_charge(1.0);
+        assert_eq!(op.calculate_talking_cost(1, 30), 1.0);
+        assert!((op.calculate_talking_cost(10, 30) - 7.0).abs() < 1e-9);
+        assert_eq!(op.calculate_talking_cost(0, 30), 0.0, "no call, no fee");
+    }
+
+    #[test]
+    fn spending_cap_blocks_a_connection_the_bill_could_take() {
+        let operators = create_operator_list();
+        let mut bills = vec![Some(Bill::new(100.0))];
+        let mut bob = Customer::new(1, "Bob", 30, 1, 0);
+        let alice = Customer::new(0, "Alice", 17, 0, 0);
+        bob.set_spending_cap(Some(1.0));
+        bob.message(5, &alice, &operators, &mut bills);
+        assert!((bob.period_spend - 0.4).abs() < 1e-9);
+
+        // 100 MB costs 1.50: the bill has room, the cap doesn't
+        bob.connection(100.0, &operators, &mut bills);
+        assert!(bills[0].as_ref().unwrap().check(1.5));
+        assert!((bills[0].as_ref().unwrap().get_current_debt() - 0.4).abs() < 1e-9);
+
+        let mut customers = vec![Some(bob)];
+        close_month(&mut customers, &mut bills, 0.0);
+        let bob = customers[0].as_mut().unwrap();
+        bob.connection(50.0, &operators, &mut bills);
+        assert!((bob.period_spend - 0.75).abs() < 1e-9, "the cap starts over each month");
+    }
+
+    #[test]
+    fn merged_bill_takes_over_the_debt_and_ledger() {
+        let mut main = Bill::new(50.0);
+        main.add(10.0);
+        let mut old = Bill::new(80.0);
+        old.add(4.0);
+        old.pay(1.5);
+        main.merge(&mut old, MergePolicy::KeepLarger);
+
+        assert_eq!(main.get_current_debt(), 12.5);
+        assert_eq!(main.get_limiting_amount(), 80.0);
+        let kinds: Vec<LedgerKind> = main.ledger.iter().map(|e| e.kind).collect();
+        assert_eq!(kinds, vec![LedgerKind::Charge, LedgerKind::Charge, LedgerKind::Payment]);
+        assert_eq!(old.get_current_debt(), 0.0);
+        assert!(old.ledger.is_empty());
+
+        let mut extra = Bill::new(20.0);
+        main.merge(&mut extra, MergePolicy::Sum);
+        assert_eq!(main.get_limiting_amount(), 100.0);
+    }
+
+    #[test]
+    fn ageing_past_65_brings_back_the_discount() {
+        let operators = create_operator_list();
+        let mut bills = vec![Some(Bill::new(100.0))];
+        let mut customers = vec![Some(Customer::new(0, "Dan", 64, 0, 0))];
+        let peer = Customer::new(1, "Peer", 30, 0, 0);
+        let debt = |bills: &Vec<Option<Bill>>| bills[0].as_ref().unwrap().get_current_debt();
+
+        customers[0].as_mut().unwrap().talk(10, &peer, &operators, &mut bills);
+        assert_eq!(debt(&bills), 5.0);
+
+        advance_time(&mut customers, 2);
+        assert_eq!(customers[0].as_ref().unwrap().get_age(), 66);
+        customers[0].as_mut().unwrap().talk(10, &peer, &operators, &mut bills);
+        assert_eq!(debt(&bills), 9.5, "the second call is 10% off");
+    }
+
+    #[test]
+    fn half_a_cent_is_rounded_per_mode() {
+        for (mode, expected) in [(RoundingMode::HalfUp, 0.13), (RoundingMode::Bankers, 0.12), (RoundingMode::Truncate, 0.12)] {
+            let mut bill = Bill::new(10.0);
+            bill.set_rounding(mode);
+            bill.add(0.125);
+            assert_eq!(bill.get_current_debt(), expected, "{:?}", mode);
+            assert_eq!(bill.ledger[0].amount, expected, "{:?}", mode);
+        }
+    }
+
+    #[test]
+    fn only_minutes_past_the_first_100_are_billed() {
+        let mut operators = create_operator_list();
+        operators[1].as_mut().unwrap().set_free_minutes(100);
+        let mut bills = vec![Some(Bill::new(1000.0))];
+        let mut bob = Customer::new(1, "Bob", 30, 1, 0);
+        let alice = Customer::new(0, "Alice", 17, 0, 0);
+        let debt = |bills: &Vec<Option<Bill>>| bills[0].as_ref().unwrap().get_current_debt();
+
+        bob.talk(60, &alice, &operators, &mut bills);
+        assert_eq!(debt(&bills), 0.0);
+        // 40 of these 50 minutes are still free
+        bob.talk(50, &alice, &operators, &mut bills);
+        assert!((debt(&bills) - 7.0).abs() < 1e-9, "debt {}", debt(&bills));
+        bob.talk(10, &alice, &operators, &mut bills);
+        assert!((debt(&bills) - 14.0).abs() < 1e-9, "debt {}", debt(&bills));
+        assert_eq!(bob.used_minutes, 120);
+    }
+
+    #[test]
+    fn ledger_csv_lists_every_movement_with_a_running_balance() {
+        let mut bill = Bill::new(100.0);
+        bill.add(10.0);
+        bill.add(5.5);
+        bill.pay(8.0);
+        bill.add_exempt();
+        bill.accrue_interest(0.1);
+        let csv = bill.ledger_csv();
+        assert_eq!(csv, "index,category,amount,balance\n\
+                         0,charge,10.00,10.00\n\
+                         1,charge,5.50,15.50\n\
+                         2,payment,8.00,7.50\n\
+                         3,exempt,0.00,7.50\n\
+                         4,interest,0.75,8.25\n");
+        let last = csv.lines().last().unwrap();
+        assert_eq!(last.rsplit(',').next(), Some(format!("{:.2}", bill.get_current_debt()).as_str()));
+    }
+
+    #[test]
+    fn tagged_customer_gets_a_discount_an_untagged_peer_does_not() {
+        let mut operators = create_operator_list();
+        operators[1].as_mut().unwrap().set_tag_discount("student", 25);
+        let mut tagged = Customer::new(0, "Tess", 30, 1, 0);
+        tagged.add_tag("student");
+        let plain = Customer::new(1, "Paul", 30, 1, 0);
+        assert!((plain.quote_talk(10, &operators).unwrap() - 7.0).abs() < 1e-9);
+        assert!((tagged.quote_talk(10, &operators).unwrap() - 5.25).abs() < 1e-9);
+
+        // an unknown tag changes nothing, and discounts don't stack with age
+        let mut teen = Customer::new(2, "Tim", 17, 1, 0);
+        teen.add_tag("gamer");
+        assert!((teen.quote_talk(10, &operators).unwrap() - 6.65).abs() < 1e-9);
+        teen.add_tag("student");
+        assert!((teen.quote_talk(10, &operators).unwrap() - 5.25).abs() < 1e-9);
+    }
+
+    #[test]
+    fn charged_equals_paid_plus_outstanding() {
+        let mut bills = vec![Some(Bill::new(100.0)), Some(Bill::new(100.0)), None, Some(Bill::new(100.0))];
+        bills[0].as_mut().unwrap().add_for_operator(20.0, 0);
+        bills[0].as_mut().unwrap().pay_full();
+        bills[1].as_mut().unwrap().add_for_operator(30.0, 1);
+        bills[1].as_mut().unwrap().pay(10.0);
+        bills[3].as_mut().unwrap().add(5.0);
+        bills[3].as_mut().unwrap().accrue_interest(0.1);
+
+        let summary = financial_summary(&bills, &operator_revenue(&bills));
+        assert!((summary.charged - 55.5).abs() < 1e-9);
+        assert!((summary.paid - 30.0).abs() < 1e-9);
+        assert!((summary.outstanding - 25.5).abs() < 1e-9);
+        assert!((summary.charged - summary.paid - summary.outstanding).abs() < 1e-9);
+        assert!((summary.operator_revenue - 50.0).abs() < 1e-9, "the unattributed charge and the interest are no operator's");
+        assert!((summary.collection_rate.unwrap() - 30.0 / 55.5).abs() < 1e-9);
+        assert!(financial_summary(&[], &HashMap::new()).collection_rate.is_none());
+    }
+}
@@ -1,6 +1,7 @@
 use serde::{Deserialize, Serialize};
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
 use std::fs;
+use std::time::{Duration, Instant};
 use std::f64::consts::PI;
 
 fn haversine_km(lat1: f64, lon1: f64, lat2: f64, lon2: f64) -> f64 {
@@ -15,13 +16,53 @@ fn haversine_km(lat1: f64, lon1: f64, lat2: f64, lon2: f64) -> f64 {
     r * c
 }
 
+// optional settings every container kind can carry; flattened into each variant
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ContainerExtras {
+    // replaces the per-weight consumption for an atypical unit (e.g. a damaged reefer)
+    #[serde(default)]
+    pub consumption_override: Option<f64>,
+    // declared cargo value, derived from the weight when absent
+    #[serde(default)]
+    pub value: Option<f64>,
+    // per-voyage draw independent of weight (e.g. reefer power), added to the weight term
+    #[serde(default)]
+    pub flat_consumption: f64,
+    // containers sharing a consignment id are meant to travel together
+    #[serde(default)]
+    pub consignment: Option<usize>,
+}
+
+// value of a container with no declared value, per unit of weight
+const DEFAULT_VALUE_PER_WEIGHT: f64 = 1.0;
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(tag = "type")]
 pub enum ContainerData {
-    Basic { id: usize, weight: i32 },
-    Heavy { id: usize, weight: i32 },
-    Refrigerated { id: usize, weight: i32 },
-    Liquid { id: usize, weight: i32 },
+    Basic {
+        id: usize,
+        weight: i32,
+        #[serde(flatten)]
+        extras: ContainerExtras,
+    },
+    Heavy {
+        id: usize,
+        weight: i32,
+        #[serde(flatten)]
+        extras: ContainerExtras,
+    },
+    Refrigerated {
+        id: usize,
+        weight: i32,
+        #[serde(flatten)]
+        extras: ContainerExtras,
+    },
+    Liquid {
+        id: usize,
+        weight: i32,
+        #[serde(flatten)]
+        extras: ContainerExtras,
+    },
 }
 
 impl ContainerData {
@@ -41,6 +82,17 @@ impl ContainerData {
             ContainerData::Liquid { weight, .. } => *weight,
         }
     }
+    // a plain container is reclassified as at creation (basic up to 3000, heavy above);
+    // refrigerated and liquid containers keep their kind
+    pub fn set_weight(&mut self, new_weight: i32) {
+        *self = match self.clone() {
+            ContainerData::Basic { id, extras, .. } | ContainerData::Heavy { id, extras, .. } => {
+                if new_weight <= 3000 { ContainerData::Basic { id, weight: new_weight, extras } } else { ContainerData::Heavy { id, weight: new_weight, extras } }
+            }
+            ContainerData::Refrigerated { id, extras, .. } => ContainerData::Refrigerated { id, weight: new_weight, extras },
+            ContainerData::Liquid { id, extras, .. } => ContainerData::Liquid { id, weight: new_weight, extras },
+        };
+    }
     pub fn consumption_per_unit(&self) -> f64 {
         match self {
             ContainerData::Basic { .. } => 2.50,
@@ -49,9 +101,29 @@ impl ContainerData {
             ContainerData::Liquid { .. } => 4.00,
         }
     }
+    pub fn extras(&self) -> &ContainerExtras {
+        match self {
+            ContainerData::Basic { extras, .. } => extras,
+            ContainerData::Heavy { extras, .. } => extras,
+            ContainerData::Refrigerated { extras, .. } => extras,
+            ContainerData::Liquid { extras, .. } => extras,
+        }
+    }
+    pub fn consumption_override(&self) -> Option<f64> {
+        self.extras().consumption_override
+    }
+    pub fn consignment(&self) -> Option<usize> {
+        self.extras().consignment
+    }
     pub fn total_consumption(&self) -> f64 {
-        self.consumption_per_unit() * (self.weight() as f64)
+        self.consumption_override().unwrap_or_else(|| self.consumption_per_unit() * (self.weight() as f64)) + self.extras().flat_consumption
+    }
+    pub fn value(&self) -> f64 {
+        self.extras().value.unwrap_or_else(|| DEFAULT_VALUE_PER_WEIGHT * (self.weight() as f64))
     }
+    // the lowercase kind names used in queries, as returned by `kind_name`
+    pub const KINDS: [&'static str; 4] = ["basic", "heavy", "refrigerated", "liquid"];
+
     pub fn kind_name(&self) -> &'static str {
         match self {
             ContainerData::Basic { .. } => "basic",
@@ -62,16 +134,59 @@ impl ContainerData {
     }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ShipOpError {
+    UnknownPort,
+    NotEnoughFuel,
+    NoBerthAvailable,
+    ContainerNotOnShip,
+    BuriedContainer,
+    AlreadyThere, // sailing to the current port; nothing is touched
+    NoFuelAtPort,
+    ShipOutOfService,
+    UnknownContainer,
+    ContainerNotInPort,
+    WeightCapacityExceeded,
+    ContainerLimitExceeded,
+    HeavyLimitExceeded,
+    RefrigeratedLimitExceeded,
+    LiquidLimitExceeded,
+    LiquidWeightExceeded,
+    RefrigeratedWeightExceeded,
+    KindNotAllowed, // the ship's allow-list excludes this container kind
+}
+
+// how freely containers can be taken off a ship
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StackMode {
+    #[default]
+    Any,
+    Lifo, // only the most recently loaded container can be unloaded
+}
+
+// how `auto_load` picks containers from the port
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LoadStrategy {
+    #[default]
+    Greedy,  // heaviest first, take whatever still fits
+    BestFit, // the combination that uses the most weight capacity
+}
+
+// exact best-fit is exponential, so beyond this many candidates auto_load falls back to greedy
+const BEST_FIT_MAX_CANDIDATES: usize = 20;
+
 pub trait IPort {
     fn incoming_ship(&mut self, s_id: usize); // add to current if not present
     fn outgoing_ship(&mut self, s_id: usize); // add to history if not duplicate
 }
 
 pub trait IShip {
-    fn sail_to(&mut self, dest_port_id: usize, ports: &mut HashMap<usize, Port>, ships: &mut HashMap<usize, Ship>) -> bool;
+    fn sail_to(&mut self, dest_port_id: usize, ports: &mut HashMap<usize, Port>, container_store: &HashMap<usize, ContainerData>, fuel_density: f64) -> Result<(), ShipOpError>;
     fn re_fuel(&mut self, amount: f64);
-    fn load(&mut self, cont_id: usize, ports: &mut HashMap<usize, Port>, container_store: &mut HashMap<usize, ContainerData>) -> bool;
-    fn un_load(&mut self, cont_id: usize, ports: &mut HashMap<usize, Port>, container_store: &mut mut_ref) -> bool;
+    fn load(&mut self, cont_id: usize, ports: &mut HashMap<usize, Port>, container_store: &mut HashMap<usize, ContainerData>) -> Result<(), ShipOpError>;
+    fn un_load(&mut self, cont_id: usize, ports: &mut HashMap<usize, Port>, container_store: &mut mut_ref) -> Result<(), ShipOpError>;
 }
 
 type mut_ref = HashMap<usize, ContainerData>;
@@ -81,17 +196,59 @@ pub struct Port {
     pub id: usize,
     pub latitude: f64,
     pub longitude: f64,
+    #[serde(default)]
+    pub name: Option<String>,
+    #[serde(default = "unlimited_berths")]
+    pub max_berths: usize,             // how many ships can be docked at once
+    #[serde(default = "fuel_on_sale")]
+    pub has_fuel: bool,                // whether ships can refuel here
     #[serde(skip)]
     pub containers: HashSet<usize>, // container IDs present in port
     #[serde(skip)]
     pub history: HashSet<usize>,    // ship IDs that visited
     #[serde(skip)]
     pub current: HashSet<usize>,    // ship IDs currently here
+    #[serde(skip)]
+    pub waiting: Vec<usize>,        // ship IDs turned away for lack of a berth, still wanting to dock
+    #[serde(skip)]
+    pub weather: HashMap<usize, f64>, // fuel multiplier for legs leaving here, keyed by destination port ID
+    #[serde(skip)]
+    pub departed: usize, // containers loaded out of the yard onto ships
+    #[serde(skip)]
+    pub arrived: usize,  // containers unloaded into the yard; created cargo counts as originating here
+}
+
+fn unlimited_berths() -> usize {
+    usize::MAX
+}
+
+fn fuel_on_sale() -> bool {
+    true
 }
 
 impl Port {
     pub fn new(id: usize, latitude: f64, longitude: f64) -> Self {
-        Self { id, latitude, longitude, containers: HashSet::new(), history: HashSet::new(), current: HashSet::new() }
+        Self { id, latitude, longitude, name: None, max_berths: unlimited_berths(), has_fuel: fuel_on_sale(), containers: HashSet::new(), history: HashSet::new(), current: HashSet::new(), waiting: Vec::new(), weather: HashMap::new(), departed: 0, arrived: 0 }
+    }
+    // human-readable name, falling back to "Port N"
+    pub fn label(&self) -> String {
+        self.name.clone().unwrap_or_else(|| format!("Port {}", self.id))
+    }
+    pub fn has_free_berth(&self) -> bool {
+        self.current.len() < self.max_berths
+    }
+    // weight of the containers sitting in the yard; ids missing from the store are skipped
+    // (the output pass already reports them as warnings)
+    pub fn total_container_weight(&self, container_store: &HashMap<usize, ContainerData>) -> i64 {
+        self.containers.iter().filter_map(|cid| container_store.get(cid)).map(|c| c.weight() as i64).sum()
+    }
+    // 1.0 unless a storm was set on the leg from here to `dest_id`
+    pub fn weather_multiplier(&self, dest_id: usize) -> f64 {
+        self.weather.get(&dest_id).copied().unwrap_or(1.0)
+    }
+    // containers that left minus containers that came in; positive for a net source of cargo
+    pub fn net_flow(&self) -> i64 {
+        self.departed as i64 - self.arrived as i64
     }
     pub fn get_distance(&self, other: &Port) -> f64 {
         haversine_km(self.latitude, self.longitude, other.latitude, other.longitude)
@@ -121,9 +278,43 @@ pub struct Ship {
     pub max_number_of_refrigerated_containers: usize,
     pub max_number_of_liquid_containers: usize,
     pub fuel_consumption_per_km: f64,
+    #[serde(default = "unlimited_fuel")]
+    pub max_fuel_capacity: f64,
+    #[serde(default)]
+    pub stacking: StackMode,
+    #[serde(default = "unlimited_weight")]
+    pub max_liquid_weight: i32,       // per-kind weight caps, on top of the total
+    #[serde(default = "unlimited_weight")]
+    pub max_refrigerated_weight: i32,
+    #[serde(default = "in_service_by_default")]
+    pub in_service: bool,             // false while in maintenance: no sailing, loading or unloading
+    #[serde(default)]
+    pub allowed_kinds: Option<Vec<String>>, // kind names from ContainerData::KINDS; None carries anything
+    #[serde(default)]
+    pub wear_per_1000km: f64,         // fractional rise in consumption per 1000 km since the last Maintain
 
     #[serde(skip)]
-    pub containers: Vec<usize>, // container IDs currently on ship
+    pub containers: Vec<usize>, // container IDs currently on ship, in load order
+    #[serde(skip)]
+    pub km_sailed: f64, // voyage totals over the run
+    #[serde(skip)]
+    pub ports_visited: Vec<usize>, // starting port, then every port sailed to, in order
+    #[serde(skip)]
+    pub fuel_burned: f64,
+    #[serde(skip)]
+    pub km_since_maintenance: f64,
+}
+
+fn unlimited_fuel() -> f64 {
+    f64::INFINITY
+}
+
+fn unlimited_weight() -> i32 {
+    i32::MAX
+}
+
+fn in_service_by_default() -> bool {
+    true
 }
 
 impl Ship {
@@ -147,7 +338,34 @@ impl Ship {
             max_number_of_refrigerated_containers,
             max_number_of_liquid_containers,
             fuel_consumption_per_km,
+            max_fuel_capacity: unlimited_fuel(),
+            stacking: StackMode::Any,
+            max_liquid_weight: unlimited_weight(),
+            max_refrigerated_weight: unlimited_weight(),
+            in_service: in_service_by_default(),
+            allowed_kinds: None,
+            containers: Vec::new(),
+            km_sailed: 0.0,
+            ports_visited: vec![current_port],
+            fuel_burned: 0.0,
+            wear_per_1000km: 0.0,
+            km_since_maintenance: 0.0,
+        }
+    }
+
+    // same capacities, consumption and stacking as `self`, but empty, unfuelled and in service at `port_id`
+    pub fn clone_as(&self, new_id: usize, port_id: usize) -> Self {
+        Self {
+            id: new_id,
+            fuel: 0.0,
+            current_port: port_id,
+            in_service: true,
             containers: Vec::new(),
+            km_sailed: 0.0,
+            ports_visited: vec![port_id],
+            fuel_burned: 0.0,
+            km_since_maintenance: 0.0,
+            ..self.clone()
         }
     }
 
@@ -157,306 +375,3922 @@ impl Ship {
         v
     }
 
-    fn current_total_weight(&self, container_store: &HashMap<usize, ContainerData>) -> i32 {
-        self.containers.iter().map(|id| container_store.get(id).unwrap().weight()).sum()
+    // the containers aboard; an id missing from the store weighs nothing and counts for nothing
+    // (the output pass reports it as a warning)
+    fn cargo<'a>(&'a self, container_store: &'a HashMap<usize, ContainerData>) -> impl Iterator<Item = &'a ContainerData> + 'a {
+        self.containers.iter().filter_map(move |id| {
+            let cont = container_store.get(id);
+            if cont.is_none() {
+                log::debug!("ship {} holds container {} which is missing from the store", self.id, id);
+            }
+            cont
+        })
+    }
+
+    // weight sums are i64 so a full hold of heavy containers can't wrap past i32::MAX
+    fn current_total_weight(&self, container_store: &HashMap<usize, ContainerData>) -> i64 {
+        self.cargo(container_store).map(|c| c.weight() as i64).sum()
     }
     fn current_heavy_count(&self, container_store: &HashMap<usize, ContainerData>) -> usize {
-        self.containers.iter().filter(|id| matches!(container_store.get(id).unwrap(), ContainerData::Heavy {..} | ContainerData::Refrigerated {..} | ContainerData::Liquid {..})).count()
+        self.cargo(container_store).filter(|c| matches!(c, ContainerData::Heavy {..} | ContainerData::Refrigerated {..} | ContainerData::Liquid {..})).count()
     }
     fn current_refrigerated_count(&self, container_store: &HashMap<usize, ContainerData>) -> usize {
-        self.containers.iter().filter(|id| matches!(container_store.get(id).unwrap(), ContainerData::Refrigerated {..})).count()
+        self.cargo(container_store).filter(|c| matches!(c, ContainerData::Refrigerated {..})).count()
     }
     fn current_liquid_count(&self, container_store: &HashMap<usize, ContainerData>) -> usize {
-        self.containers.iter().filter(|id| matches!(container_store.get(id).unwrap(), ContainerData::Liquid {..})).count()
+        self.cargo(container_store).filter(|c| matches!(c, ContainerData::Liquid {..})).count()
     }
-}
-
-impl IShip for Ship {
-    fn sail_to(&mut self, dest_port_id: usize, ports: &mut HashMap<usize, Port>, ships: &mut HashMap<usize, Ship>) -> bool {
-        // calculate distance
-        let current_port = ports.get(&self.current_port).unwrap().clone();
-        let dest_port = match ports.get(&dest_port_id) {
-            Some(p) => p.clone(),
-            None => return false,
-        };
-        let km = current_port.get_distance(&dest_port);
-        // base consumption
-        let mut required = km * self.fuel_consumption_per_km;
-        if self.fuel >= required {
-            ports.get_mut(&self.current_port).unwrap().outgoing_ship(self.id);
-            self.fuel -= required;
-            self.current_port = dest_port_id;
-            ports.get_mut(&dest_port_id).unwrap().incoming_ship(self.id);
-            true
-        } else {
-            false
-        }
+    fn current_liquid_weight(&self, container_store: &HashMap<usize, ContainerData>) -> i64 {
+        self.cargo(container_store).filter(|c| matches!(c, ContainerData::Liquid {..})).map(|c| c.weight() as i64).sum()
     }
-
-    fn re_fuel(&mut self, amount: f64) {
-        self.fuel += amount;
+    fn current_refrigerated_weight(&self, container_store: &HashMap<usize, ContainerData>) -> i64 {
+        self.cargo(container_store).filter(|c| matches!(c, ContainerData::Refrigerated {..})).map(|c| c.weight() as i64).sum()
     }
 
-    fn load(&mut self, cont_id: usize, ports: &mut HashMap<usize, Port>, container_store: &mut HashMap<usize, ContainerData>) -> bool {
+    // every check a container must pass to come aboard; the single enforcement point for
+    // anything that puts containers on a ship
+    pub fn can_load(&self, cont_id: usize, ports: &HashMap<usize, Port>, container_store: &HashMap<usize, ContainerData>) -> Result<(), ShipOpError> {
+        if !self.in_service {
+            return Err(ShipOpError::ShipOutOfService);
+        }
         // check container exists in port
-        let port = ports.get_mut(&self.current_port).unwrap();
+        let port = ports.get(&self.current_port).unwrap();
         if !port.containers.contains(&cont_id) {
-            return false;
+            return Err(ShipOpError::ContainerNotInPort);
         }
         // capacity checks
-        let cont = container_store.get(&cont_id).unwrap();
-        let new_weight = self.current_total_weight(container_store) + cont.weight();
-        if new_weight > self.total_weight_capacity {
-            return false;
+        let cont = container_store.get(&cont_id).ok_or(ShipOpError::UnknownContainer)?;
+        if !self.carries_kind(cont) {
+            return Err(ShipOpError::KindNotAllowed);
+        }
+        let new_weight = self.current_total_weight(container_store) + cont.weight() as i64;
+        if new_weight > self.total_weight_capacity as i64 {
+            return Err(ShipOpError::WeightCapacityExceeded);
         }
         if self.containers.len() + 1 > self.max_number_of_all_containers {
-            return false;
+            return Err(ShipOpError::ContainerLimitExceeded);
         }
         let heavy_count = self.current_heavy_count(container_store) + if matches!(cont, ContainerData::Heavy {..} | ContainerData::Refrigerated {..} | ContainerData::Liquid {..}) {1} else {0};
         if heavy_count > self.max_number_of_heavy_containers {
-            return false;
+            return Err(ShipOpError::HeavyLimitExceeded);
         }
         let ref_count = self.current_refrigerated_count(container_store) + if matches!(cont, ContainerData::Refrigerated{..}) {1} else {0};
         if ref_count > self.max_number_of_refrigerated_containers {
-            return false;
+            return Err(ShipOpError::RefrigeratedLimitExceeded);
         }
         let liq_count = self.current_liquid_count(container_store) + if matches!(cont, ContainerData::Liquid{..}) {1} else {0};
         if liq_count > self.max_number_of_liquid_containers {
-            return false;
+            return Err(ShipOpError::LiquidLimitExceeded);
         }
-        port.containers.remove(&cont_id);
-        self.containers.push(cont_id);
-        true
-    }
-
-    fn un_load(&mut self, cont_id: usize, ports: &mut HashMap<usize, Port>, container_store: &mut mut_ref) -> bool {
-        if let Some(pos) = self.containers.iter().position(|&x| x == cont_id) {
-            self.containers.swap_remove(pos);
-            ports.get_mut(&self.current_port).unwrap().containers.insert(cont_id);
-            return true;
+        if matches!(cont, ContainerData::Liquid{..}) && self.current_liquid_weight(container_store) + cont.weight() as i64 > self.max_liquid_weight as i64 {
+            return Err(ShipOpError::LiquidWeightExceeded);
         }
-        false
+        if matches!(cont, ContainerData::Refrigerated{..}) && self.current_refrigerated_weight(container_store) + cont.weight() as i64 > self.max_refrigerated_weight as i64 {
+            return Err(ShipOpError::RefrigeratedWeightExceeded);
+        }
+        Ok(())
     }
-}
-
-#[derive(Deserialize)]
-#[serde(rename_all = "lowercase")]
-enum Operation {
-    CreatePort { id: usize, latitude: f64, longitude: f64 },
-    CreateShip { id: usize, port_id: usize, total_weight_capacity: i32, max_number_of_all_containers: usize, max_number_of_heavy_containers: usize, max_number_of_refrigerated_containers: usize, max_number_of_liquid_containers: usize, fuel_consumption_per_km: f64 },
-    CreateContainer { id: usize, weight: i32, special: Option<String>, port_id: usize },
-    Load { ship_id: usize, container_id: usize },
-    Unload { ship_id: usize, container_id: usize },
-    Sail { ship_id: usize, dest_port_id: usize },
-    Refuel { ship_id: usize, amount: f64 },
-}
-
-#[derive(Deserialize)]
-struct InputFile {
-    operations: Vec<Operation>,
-}
-
-#[derive(Serialize)]
-struct OutputPort {
-    lat: f64,
-    lon: f64,
-    basic_container: Vec<usize>,
-    heavy_container: Vec<usize>,
-    refrigerated_container: Vec<usize>,
-    liquid_container: Vec<usize>,
-    ships: HashMap<String, OutputShip>,
-}
 
-#[derive(Serialize)]
-struct OutputShip {
-    fuel_left: f64,
-    basic_container: Vec<usize>,
-    heavy_container: Vec<usize>,
-    refrigerated_container: Vec<usize>,
-    liquid_container: Vec<usize>,
-}
+    pub fn carries_kind(&self, cont: &ContainerData) -> bool {
+        self.allowed_kinds.as_ref().is_none_or(|kinds| kinds.iter().any(|k| k == cont.kind_name()))
+    }
 
-fn main() {
-    // for demo: read "input.json" from current directory
-    let input_text = fs::read_to_string("input.json").expect("input.json not found");
-    let input: InputFile = serde_json::from_str(&input_text).expect("invalid JSON");
+    // loads containers waiting at the current port; returns the ids that went aboard. with
+    // `keep_consignments` the consignments in the yard are tried after the loose cargo, each one whole or not at all
+    pub fn auto_load(&mut self, strategy: LoadStrategy, keep_consignments: bool, ports: &mut HashMap<usize, Port>, container_store: &mut HashMap<usize, ContainerData>) -> Vec<usize> {
+        let yard = &ports.get(&self.current_port).unwrap().containers;
+        let grouped = |id: &usize| container_store.get(id).and_then(|c| c.consignment()).filter(|_| keep_consignments);
+        let mut consignments: Vec<usize> = yard.iter().filter_map(grouped).collect();
+        consignments.sort_unstable();
+        consignments.dedup();
+        let mut candidates: Vec<usize> = yard.iter().cloned().filter(|id| grouped(id).is_none() && container_store.get(id).is_some_and(|c| self.carries_kind(c))).collect();
+        candidates.sort_by_key(|id| (std::cmp::Reverse(container_store[id].weight()), *id));
+        if strategy == LoadStrategy::BestFit && candidates.len() <= BEST_FIT_MAX_CANDIDATES {
+            candidates = self.best_fit(&candidates, container_store);
+        }
+        let mut loaded: Vec<usize> = candidates.into_iter().filter(|&cid| self.load(cid, ports, container_store).is_ok()).collect();
+        for c in consignments {
+            let members = consignment_members(container_store, c);
+            if move_consignment(self, &members, ports, container_store, |s, cid, p, cs| s.load(cid, p, cs)).is_ok() {
+                loaded.extend(members);
+            }
+        }
+        loaded
+    }
 
-    let mut ports: HashMap<usize, Port> = HashMap::new();
-    let mut ships: HashMap<usize, Ship> = HashMap::new();
-    let mut container_store: HashMap<usize, ContainerData> = HashMap::new();
+    // exhaustive search for the subset of `candidates` with the largest total weight that passes every load check
+    fn best_fit(&self, candidates: &[usize], container_store: &HashMap<usize, ContainerData>) -> Vec<usize> {
+        // per container: the weight it adds (total, liquid, refrigerated) and the slots it takes (all, heavy, refrigerated, liquid)
+        let items: Vec<([i64; 3], [usize; 4])> = candidates.iter().map(|id| {
+            let cont = &container_store[id];
+            let w = cont.weight() as i64;
+            let heavy = matches!(cont, ContainerData::Heavy {..} | ContainerData::Refrigerated {..} | ContainerData::Liquid {..}) as usize;
+            let refr = matches!(cont, ContainerData::Refrigerated {..}) as usize;
+            let liq = matches!(cont, ContainerData::Liquid {..}) as usize;
+            ([w, w * liq as i64, w * refr as i64], [1, heavy, refr, liq])
+        }).collect();
+        let slots = [
+            self.max_number_of_all_containers.saturating_sub(self.containers.len()),
+            self.max_number_of_heavy_containers.saturating_sub(self.current_heavy_count(container_store)),
+            self.max_number_of_refrigerated_containers.saturating_sub(self.current_refrigerated_count(container_store)),
+            self.max_number_of_liquid_containers.saturating_sub(self.current_liquid_count(container_store)),
+        ];
+        let weight_left = [
+            self.total_weight_capacity as i64 - self.current_total_weight(container_store),
+            self.max_liquid_weight as i64 - self.current_liquid_weight(container_store),
+            self.max_refrigerated_weight as i64 - self.current_refrigerated_weight(container_store),
+        ];
 
-    for op in input.operations {
-        match op {
-            Operation::CreatePort { id, latitude, longitude } => {
-                ports.insert(id, Port::new(id, latitude, longitude));
-            }
-            Operation::CreateShip { id, port_id, total_weight_capacity, max_number_of_all_containers, max_number_of_heavy_containers, max_number_of_refrigerated_containers, max_number_of_liquid_containers, fuel_consumption_per_km } => {
-                let mut s = Ship::new(id, port_id, total_weight_capacity, max_number_of_all_containers, max_number_of_heavy_containers, max_number_of_refrigerated_containers, max_number_of_liquid_containers, fuel_consumption_per_km);
-                // place ship at port (port must exist)
-                if let Some(p) = ports.get_mut(&port_id) {
-                    p.incoming_ship(id);
-                }
-                ships.insert(id, s);
+        fn search(i: usize, items: &[([i64; 3], [usize; 4])], weight_left: [i64; 3], slots: [usize; 4], used: i64, chosen: &mut Vec<usize>, best: &mut (i64, Vec<usize>)) {
+            if used > best.0 {
+                *best = (used, chosen.clone());
             }
-            Operation::CreateContainer { id, weight, special, port_id } => {
-                let cont = match special.as_deref() {
-                    Some("R") => ContainerData::Refrigerated { id, weight },
-                    Some("L") => ContainerData::Liquid { id, weight },
-                    _ => {
-                        if weight <= 3000 { ContainerData::Basic { id, weight } } else { ContainerData::Heavy { id, weight } }
-                    }
-                };
-                container_store.insert(id, cont);
-                if let Some(p) = ports.get_mut(&port_id) {
-                    p.containers.insert(id);
-                } else {
-                    // ignore or create port? choose to create a port placeholder at 0,0
-                    let mut p = Port::new(port_id, 0.0, 0.0);
-                    p.containers.insert(id);
-                    ports.insert(port_id, p);
-                }
+            if i == items.len() {
+                return;
             }
-            Operation::Load { ship_id, container_id } => {
-                if let Some(ship) = ships.get_mut(&ship_id) {
-                    let ok = ship.load(container_id, &mut ports, &mut container_store);
-                    if !ok {
-                        // load failed: ignore or log; here we ignore
-                    }
+            let (w, cost) = items[i];
+            if (0..3).all(|k| w[k] <= weight_left[k]) && (0..4).all(|k| cost[k] <= slots[k]) {
+                let mut rest = slots;
+                for k in 0..4 {
+                    rest[k] -= cost[k];
                 }
-            }
-            Operation::Unload { ship_id, container_id } => {
-                if let Some(ship) = ships.get_mut(&ship_id) {
-                    let _ = ship.un_load(container_id, &mut ports, &mut container_store);
+                let mut left = weight_left;
+                for k in 0..3 {
+                    left[k] -= w[k];
                 }
+                chosen.push(i);
+                search(i + 1, items, left, rest, used + w[0], chosen, best);
+                chosen.pop();
             }
-            Operation::Refuel { ship_id, amount } => {
-                if let Some(ship) = ships.get_mut(&ship_id) {
-                    ship.re_fuel(amount);
+            search(i + 1, items, weight_left, slots, used, chosen, best);
+        }
+
+        let mut best = (0, Vec::new());
+        search(0, &items, weight_left, slots, 0, &mut Vec::new(), &mut best);
+        best.1.into_iter().map(|i| candidates[i]).collect()
+    }
+
+    // unloads everything at the current port, top of the stack first; stops at the first container
+    // that can't come off and returns the ids that were offloaded. with `keep_consignments` the top
+    // container's consignment mates aboard come off with it, or it stops there
+    pub fn offload_all(&mut self, keep_consignments: bool, ports: &mut HashMap<usize, Port>, container_store: &mut HashMap<usize, ContainerData>) -> Vec<usize> {
+        let mut offloaded = Vec::new();
+        while let Some(&cid) = self.containers.last() {
+            match container_store.get(&cid).and_then(|c| c.consignment()).filter(|_| keep_consignments) {
+                Some(c) => {
+                    let members = self.consignment_aboard(c, container_store);
+                    if move_consignment(self, &members, ports, container_store, |s, cid, p, cs| s.un_load(cid, p, cs)).is_err() {
+                        break;
+                    }
+                    offloaded.extend(members);
                 }
-            }
-            Operation::Sail { ship_id, dest_port_id } => {
-                // compute total consumption including containers
-                if let Some(ship) = ships.get(&ship_id) {
-                    let current_port = ports.get(&ship.current_port).unwrap().clone();
-                    let dest = match ports.get(&dest_port_id) {
-                        Some(p) => p.clone(),
-                        None => continue,
-                    };
-                    let km = current_port.get_distance(&dest);
-                    // container consumption:
-                    let container_consumption: f64 = ship.containers.iter().map(|cid| container_store.get(cid).unwrap().total_consumption()).sum();
-                    let required = km * ship.fuel_consumption_per_km + container_consumption;
-                    // attempt to sail
-                    let ship_mut = ships.get_mut(&ship_id).unwrap();
-                    if ship_mut.fuel >= required {
-                        // sufficient fuel
-                        let _ = ship_mut.sail_to(dest_port_id, &mut ports, &mut ships);
-                        // NOTE: sail_to consumes only base fuel; we subtract container consumption here to reflect actual consumption
-                        ship_mut.fuel -= container_consumption;
-                    } else {
-                        // find nearest port to current to refuel
-                        let mut nearest_id: Option<usize> = None;
-                        let mut nearest_dist = std::f64::MAX;
-                        for (&pid, p) in ports.iter() {
-                            if pid == ship.current_port { continue; }
-                            let d = current_port.get_distance(p);
-                            if d < nearest_dist {
-                                nearest_dist = d;
-                                nearest_id = Some(pid);
-                            }
-                        }
-                        if let Some(npid) = nearest_id {
-                            // sail to nearest if we have enough fuel for that leg (compute cost first leg + container consumption)
-                            let leg_km = current_port.get_distance(&ports.get(&npid).unwrap());
-                            let req_leg = leg_km * ship.fuel_consumption_per_km + container_consumption;
-                            if ship_mut.fuel >= req_leg {
-                                let _ = ship_mut.sail_to(npid, &mut ports, &mut ships);
-                                ship_mut.fuel -= container_consumption; // subtract container consumption for that leg
-                                // then refuel full arbitrary amount (for simplicity add a big amount)
-                                ship_mut.re_fuel(10000.0);
-                                // finally attempt to sail to dest
-                                let current_port_after = ports.get(&ship_mut.current_port).unwrap().clone();
-                                let km2 = current_port_after.get_distance(&dest);
-                                let required2 = km2 * ship_mut.fuel_consumption_per_km + container_consumption;
-                                if ship_mut.fuel >= required2 {
-                                    let _ = ship_mut.sail_to(dest_port_id, &mut ports, &mut ships);
-                                    ship_mut.fuel -= container_consumption;
-                                }
-                            } else {
-                                // cannot reach nearest port - do nothing
-                            }
-                        }
+                None => {
+                    if self.un_load(cid, ports, container_store).is_err() {
+                        break;
                     }
+                    offloaded.push(cid);
                 }
             }
         }
+        offloaded
     }
 
-    let mut out_map: serde_json::Map<String, serde_json::Value> = serde_json::Map::new();
-    let mut port_ids: Vec<_> = ports.keys().cloned().collect();
-    port_ids.sort_unstable();
-    for pid in port_ids {
-        let p = ports.get(&pid).unwrap();
-        // containers by kind
-        let mut basic = Vec::new();
-        let mut heavy = Vec::new();
-        let mut refrigerated = Vec::new();
-        let mut liquid = Vec::new();
-        for &cid in p.containers.iter() {
-            let cont = container_store.get(&cid).unwrap();
-            match cont {
-                ContainerData::Basic { .. } => basic.push(cid),
-                ContainerData::Heavy { .. } => heavy.push(cid),
-                ContainerData::Refrigerated { .. } => refrigerated.push(cid),
-                ContainerData::Liquid { .. } => liquid.push(cid),
-            }
+    // the members of a consignment on this ship, newest first, so a LIFO stack can give them up in turn
+    fn consignment_aboard(&self, consignment: usize, container_store: &HashMap<usize, ContainerData>) -> Vec<usize> {
+        self.containers.iter().rev().copied().filter(|cid| container_store.get(cid).and_then(|c| c.consignment()) == Some(consignment)).collect()
+    }
+
+    // fuel burned per voyage by the loaded containers, independent of distance
+    pub fn container_consumption(&self, container_store: &HashMap<usize, ContainerData>) -> f64 {
+        self.cargo(container_store).map(|c| c.total_consumption()).sum()
+    }
+
+    // how far the ship can go on its current fuel once the cargo consumption is paid
+    pub fn remaining_range_km(&self, container_store: &HashMap<usize, ContainerData>, fuel_density: f64) -> f64 {
+        let usable = (self.fuel / fuel_density - self.container_consumption(container_store)).max(0.0);
+        let rate = self.worn_consumption_per_km();
+        // CreateShip rejects such rates; a ship deserialized some other way reports no range
+        if !(rate.is_finite() && rate > 0.0) {
+            return 0.0;
         }
-        basic.sort_unstable();
-        heavy.sort_unstable();
-        refrigerated.sort_unstable();
-        liquid.sort_unstable();
-        let mut ship_map = serde_json::Map::new();
-        let mut ship_ids: Vec<_> = p.current.iter().cloned().collect();
-        ship_ids.sort_unstable();
-        for sid in ship_ids {
-            let s = ships.get(&sid).unwrap();
-            let mut sbasic = Vec::new();
-            let mut sheavy = Vec::new();
-            let mut sref = Vec::new();
-            let mut sliq = Vec::new();
-            for &cid in s.get_current_containers_sorted().iter() {
-                let cont = container_store.get(&cid).unwrap();
-                match cont {
-                    ContainerData::Basic { .. } => sbasic.push(cid),
-                    ContainerData::Heavy { .. } => sheavy.push(cid),
-                    ContainerData::Refrigerated { .. } => sref.push(cid),
-                    ContainerData::Liquid { .. } => sliq.push(cid),
-                }
-            }
-            let sjson = serde_json::json!({
-                "fuel_left": (s.fuel * 100.0).round() / 100.0, // 2 decimals
-                "basic_container": sbasic,
-                "heavy_container": sheavy,
-                "refrigerated_container": sref,
-                "liquid_container": sliq
-            });
-            ship_map.insert(format!("ship_{}", sid), sjson);
+        usable / rate
+    }
+
+    // `fuel_consumption_per_km` raised by the wear built up since the last maintenance
+    pub fn worn_consumption_per_km(&self) -> f64 {
+        self.fuel_consumption_per_km * (1.0 + self.wear_per_1000km * self.km_since_maintenance / 1000.0)
+    }
+
+    // declared value of everything aboard; ids missing from the store count as nothing
+    pub fn cargo_value(&self, container_store: &HashMap<usize, ContainerData>) -> f64 {
+        self.containers.iter().filter_map(|cid| container_store.get(cid)).map(|c| c.value()).sum()
+    }
+
+    // realized efficiency including cargo burn; None until the ship has burned any fuel
+    pub fn km_per_fuel(&self) -> Option<f64> {
+        if self.fuel_burned > 0.0 { Some(self.km_sailed / self.fuel_burned) } else { None }
+    }
+
+    // tank level as a percentage; an unlimited tank counts as full whenever it holds anything
+    pub fn fuel_percent(&self) -> f64 {
+        if self.max_fuel_capacity.is_infinite() {
+            if self.fuel > 0.0 { 100.0 } else { 0.0 }
+        } else if self.max_fuel_capacity <= 0.0 {
+            0.0
+        } else {
+            self.fuel / self.max_fuel_capacity * 100.0
         }
+    }
 
-        let port_json = serde_json::json!({
-            "lat": (p.latitude * 100.0).round() / 100.0,
-            "lon": (p.longitude * 100.0).round() / 100.0,
-            "basic_container": basic,
-            "heavy_container": heavy,
-            "refrigerated_container": refrigerated,
-            "liquid_container": liquid,
-            "ships": ship_map
-        });
-        out_map.insert(format!("Port {}", pid), port_json);
+    // fuel needed to sail from `from` to `dest` with the current cargo: distance * rate + cargo consumption
+    // `fuel_density` scales every voyage in the scenario at once (1.0 = nominal)
+    pub fn voyage_fuel_cost(&self, from: &Port, dest: &Port, container_store: &HashMap<usize, ContainerData>, fuel_density: f64) -> f64 {
+        (from.get_distance(dest) * self.worn_consumption_per_km() + self.container_consumption(container_store)) * from.weather_multiplier(dest.id) * fuel_density
     }
+}
 
-    let out_value = serde_json::Value::Object(out_map);
-    let out_text = serde_json::to_string_pretty(&out_value).unwrap();
-    fs::write("output.json", out_text).expect("unable to write output.json");
-    println!("Finished. Wrote output.json");
+impl IShip for Ship {
+    fn sail_to(&mut self, dest_port_id: usize, ports: &mut HashMap<usize, Port>, container_store: &HashMap<usize, ContainerData>, fuel_density: f64) -> Result<(), ShipOpError> {
+        if !self.in_service {
+            return Err(ShipOpError::ShipOutOfService);
+        }
+        if dest_port_id == self.current_port {
+            return Err(ShipOpError::AlreadyThere);
+        }
+        let current_port = ports.get(&self.current_port).unwrap();
+        let dest_port = match ports.get(&dest_port_id) {
+            Some(p) => p,
+            None => return Err(ShipOpError::UnknownPort),
+        };
+        // base consumption plus what the cargo burns
+        let required = self.voyage_fuel_cost(current_port, dest_port, container_store, fuel_density);
+        if self.fuel < required {
+            return Err(ShipOpError::NotEnoughFuel);
+        }
+        // the ship stays at its origin when every berth at the destination is taken
+        if !dest_port.has_free_berth() {
+            return Err(ShipOpError::NoBerthAvailable);
+        }
+        self.km_sailed += current_port.get_distance(dest_port);
+        self.km_since_maintenance += current_port.get_distance(dest_port);
+        ports.get_mut(&self.current_port).unwrap().outgoing_ship(self.id);
+        self.fuel -= required;
+        self.fuel_burned += required;
+        self.current_port = dest_port_id;
+        self.ports_visited.push(dest_port_id);
+        ports.get_mut(&dest_port_id).unwrap().incoming_ship(self.id);
+        Ok(())
+    }
+
+    fn re_fuel(&mut self, amount: f64) {
+        self.fuel = (self.fuel + amount).min(self.max_fuel_capacity);
+    }
+
+    fn load(&mut self, cont_id: usize, ports: &mut HashMap<usize, Port>, container_store: &mut HashMap<usize, ContainerData>) -> Result<(), ShipOpError> {
+        self.can_load(cont_id, ports, container_store)?;
+        let port = ports.get_mut(&self.current_port).unwrap();
+        port.containers.remove(&cont_id);
+        port.departed += 1;
+        self.containers.push(cont_id);
+        Ok(())
+    }
+
+    fn un_load(&mut self, cont_id: usize, ports: &mut HashMap<usize, Port>, container_store: &mut mut_ref) -> Result<(), ShipOpError> {
+        if !self.in_service {
+            return Err(ShipOpError::ShipOutOfService);
+        }
+        let pos = self.containers.iter().position(|&x| x == cont_id).ok_or(ShipOpError::ContainerNotOnShip)?;
+        if self.stacking == StackMode::Lifo && pos + 1 != self.containers.len() {
+            return Err(ShipOpError::BuriedContainer);
+        }
+        // exact inverse of `load`: keep the load order of the rest intact for stacked ships
+        self.containers.remove(pos);
+        let port = ports.get_mut(&self.current_port).unwrap();
+        port.containers.insert(cont_id);
+        port.arrived += 1;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Location {
+    Port(usize),
+    Ship(usize),
+    Limbo, // known to the container store but neither in a port nor on a ship
+}
+
+// ids of every container matching `pred`, ascending
+pub fn find_containers<F: Fn(&ContainerData) -> bool>(container_store: &HashMap<usize, ContainerData>, pred: F) -> Vec<usize> {
+    let mut ids: Vec<usize> = container_store.values().filter(|c| pred(c)).map(|c| c.id()).collect();
+    ids.sort_unstable();
+    ids
+}
+
+// (ship_id, count) for every ship carrying at least one container of `kind`, by ship id
+pub fn ships_carrying(ships: &HashMap<usize, Ship>, container_store: &HashMap<usize, ContainerData>, kind: &str) -> Vec<(usize, usize)> {
+    let mut out: Vec<(usize, usize)> = ships.values().map(|s| {
+        let count = s.containers.iter().filter_map(|cid| container_store.get(cid)).filter(|c| c.kind_name() == kind).count();
+        (s.id, count)
+    }).filter(|&(_, count)| count > 0).collect();
+    out.sort_unstable();
+    out
+}
+
+pub fn locate_container(id: usize, ports: &HashMap<usize, Port>, ships: &HashMap<usize, Ship>, container_store: &HashMap<usize, ContainerData>) -> Option<Location> {
+    if let Some(p) = ports.values().find(|p| p.containers.contains(&id)) {
+        return Some(Location::Port(p.id));
+    }
+    if let Some(s) = ships.values().find(|s| s.containers.contains(&id)) {
+        return Some(Location::Ship(s.id));
+    }
+    if container_store.contains_key(&id) {
+        return Some(Location::Limbo);
+    }
+    None
+}
+
+// geographic extent as (min_lat, max_lat, min_lon, max_lon); None when there are no ports.
+// a single port gives a degenerate box
+pub fn ports_bounding_box(ports: &HashMap<usize, Port>) -> Option<(f64, f64, f64, f64)> {
+    let mut it = ports.values();
+    let first = it.next()?;
+    Some(it.fold((first.latitude, first.latitude, first.longitude, first.longitude), |(lat0, lat1, lon0, lon1), p| {
+        (lat0.min(p.latitude), lat1.max(p.latitude), lon0.min(p.longitude), lon1.max(p.longitude))
+    }))
+}
+
+// km between every pair of ports; rows and columns follow ascending port id
+pub fn distance_matrix(ports: &HashMap<usize, Port>) -> Vec<Vec<f64>> {
+    let mut ids: Vec<usize> = ports.keys().cloned().collect();
+    ids.sort_unstable();
+    ids.iter().map(|a| ids.iter().map(|b| ports[a].get_distance(&ports[b])).collect()).collect()
+}
+
+// ids of ports with no containers in the yard and no ships docked, ascending
+pub fn empty_ports(ports: &HashMap<usize, Port>) -> Vec<usize> {
+    let mut ids: Vec<usize> = ports.values().filter(|p| p.containers.is_empty() && p.current.is_empty()).map(|p| p.id).collect();
+    ids.sort_unstable();
+    ids
+}
+
+// the existing port with the smallest summed distance to all the others (ties go to the lower id),
+// with that sum; None when there are no ports
+pub fn optimal_depot(ports: &HashMap<usize, Port>) -> Option<(usize, f64)> {
+    let mut best: Option<(usize, f64)> = None;
+    for p in ports.values() {
+        let total: f64 = ports.values().map(|q| p.get_distance(q)).sum();
+        if best.is_none_or(|(id, t)| total < t || (total == t && p.id < id)) {
+            best = Some((p.id, total));
+        }
+    }
+    best
+}
+
+// (in port yards, aboard ships, in the store but placed nowhere); the three add up to the
+// store size unless some container is listed twice or a listed id is missing from the store
+pub fn count_containers(ports: &HashMap<usize, Port>, ships: &HashMap<usize, Ship>, container_store: &HashMap<usize, ContainerData>) -> (usize, usize, usize) {
+    let in_ports: usize = ports.values().map(|p| p.containers.len()).sum();
+    let on_ships: usize = ships.values().map(|s| s.containers.len()).sum();
+    let placed: HashSet<usize> = ports.values().flat_map(|p| p.containers.iter()).chain(ships.values().flat_map(|s| s.containers.iter())).cloned().collect();
+    let limbo = container_store.keys().filter(|cid| !placed.contains(cid)).count();
+    (in_ports, on_ships, limbo)
+}
+
+// utilization percentages; None for a ship whose capacity is zero (or a fleet with no such ship)
+#[derive(Debug, Default, Serialize)]
+pub struct FleetUtil {
+    pub avg_weight_percent: Option<f64>,
+    pub avg_count_percent: Option<f64>,
+    pub per_ship: BTreeMap<usize, ShipUtil>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ShipUtil {
+    pub weight_percent: Option<f64>,
+    pub count_percent: Option<f64>,
+}
+
+pub fn fleet_utilization(ships: &HashMap<usize, Ship>, container_store: &HashMap<usize, ContainerData>) -> FleetUtil {
+    let percent = |used: f64, capacity: f64| if capacity > 0.0 && capacity.is_finite() { Some(100.0 * used / capacity) } else { None };
+    let mean = |xs: Vec<f64>| if xs.is_empty() { None } else { Some(xs.iter().sum::<f64>() / xs.len() as f64) };
+    let mut util = FleetUtil::default();
+    for s in ships.values() {
+        util.per_ship.insert(s.id, ShipUtil {
+            weight_percent: percent(s.current_total_weight(container_store) as f64, s.total_weight_capacity as f64),
+            count_percent: percent(s.containers.len() as f64, s.max_number_of_all_containers as f64),
+        });
+    }
+    util.avg_weight_percent = mean(util.per_ship.values().filter_map(|u| u.weight_percent).collect());
+    util.avg_count_percent = mean(util.per_ship.values().filter_map(|u| u.count_percent).collect());
+    util
+}
+
+// ships that could not reach any other port even on a full tank with their current cargo.
+// any voyage starts with a leg from the current port, so one leg decides it; unlimited tanks never strand,
+// and neither does a world with a single port, where there is nowhere to be cut off from
+pub fn stranded_ships(ships: &HashMap<usize, Ship>, ports: &HashMap<usize, Port>, container_store: &HashMap<usize, ContainerData>, fuel_density: f64) -> Vec<usize> {
+    let mut stranded: Vec<usize> = ships.values().filter(|s| {
+        let here = match ports.get(&s.current_port) {
+            Some(p) => p,
+            None => return false,
+        };
+        let mut others = ports.values().filter(|p| p.id != here.id).peekable();
+        others.peek().is_some() && others.all(|p| s.voyage_fuel_cost(here, p, container_store, fuel_density) > s.max_fuel_capacity)
+    }).map(|s| s.id).collect();
+    stranded.sort_unstable();
+    stranded
+}
+
+// the nearest other port the ship can sail to on the fuel it has now, with its distance
+// (ties go to the lower id); None when every port is out of reach
+pub fn closest_reachable(ship: &Ship, ports: &HashMap<usize, Port>, container_store: &HashMap<usize, ContainerData>, fuel_density: f64) -> Option<(usize, f64)> {
+    let here = ports.get(&ship.current_port)?;
+    let mut best: Option<(usize, f64)> = None;
+    for p in ports.values().filter(|p| p.id != here.id) {
+        if ship.voyage_fuel_cost(here, p, container_store, fuel_density) > ship.fuel {
+            continue;
+        }
+        let d = here.get_distance(p);
+        if best.is_none_or(|(id, bd)| d < bd || (d == bd && p.id < id)) {
+            best = Some((p.id, d));
+        }
+    }
+    best
+}
+
+// how the distance part of a leg's fuel is shared among the cargo
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BaseFuelSplit {
+    #[default]
+    Even,
+    ByWeight,
+}
+
+// splits the fuel of a leg of `leg_km` among the ship's cargo: the containers' own burn goes to
+// each by its total_consumption, the distance burn is shared per `split`. the shares add up to
+// `leg_fuel`, which already carries weather and density; empty when there is no cargo
+pub fn allocate_voyage_cost(ship: &Ship, leg_fuel: f64, leg_km: f64, container_store: &HashMap<usize, ContainerData>, split: BaseFuelSplit) -> HashMap<usize, f64> {
+    let cargo: Vec<&ContainerData> = ship.cargo(container_store).collect();
+    if cargo.is_empty() {
+        return HashMap::new();
+    }
+    let base = leg_km * ship.worn_consumption_per_km();
+    let own: f64 = cargo.iter().map(|c| c.total_consumption()).sum();
+    let (base_fuel, own_fuel) = if base + own > 0.0 { (leg_fuel * base / (base + own), leg_fuel * own / (base + own)) } else { (leg_fuel, 0.0) };
+    let total_weight: i64 = cargo.iter().map(|c| c.weight() as i64).sum();
+    cargo.iter().map(|c| {
+        let own_share = if own > 0.0 { own_fuel * c.total_consumption() / own } else { 0.0 };
+        let base_share = match split {
+            BaseFuelSplit::ByWeight if total_weight > 0 => base_fuel * c.weight() as f64 / total_weight as f64,
+            _ => base_fuel / cargo.len() as f64,
+        };
+        (c.id(), own_share + base_share)
+    }).collect()
+}
+
+// weight-averaged (lat, lon) of the containers sitting in port yards; None when there is no yard cargo
+pub fn cargo_centroid(ports: &HashMap<usize, Port>, container_store: &HashMap<usize, ContainerData>) -> Option<(f64, f64)> {
+    let (mut lat, mut lon, mut total) = (0.0, 0.0, 0.0);
+    for p in ports.values() {
+        let w = p.total_container_weight(container_store) as f64;
+        lat += p.latitude * w;
+        lon += p.longitude * w;
+        total += w;
+    }
+    if total > 0.0 { Some((lat / total, lon / total)) } else { None }
+}
+
+// "R" and "L" pick refrigerated and liquid; anything else is basic up to 3000 and heavy above
+fn build_container(id: usize, weight: i32, special: Option<&str>, extras: ContainerExtras) -> ContainerData {
+    match special {
+        Some("R") => ContainerData::Refrigerated { id, weight, extras },
+        Some("L") => ContainerData::Liquid { id, weight, extras },
+        _ => {
+            if weight <= 3000 { ContainerData::Basic { id, weight, extras } } else { ContainerData::Heavy { id, weight, extras } }
+        }
+    }
+}
+
+// puts a container into a port's yard, creating a placeholder port at 0,0 when it is unknown
+fn place_container(id: usize, port_id: usize, ports: &mut HashMap<usize, Port>, port_index: &mut PortIndex) {
+    if let Some(p) = ports.get_mut(&port_id) {
+        p.containers.insert(id);
+    } else {
+        log::info!("Container {} refers to unknown port {}; created a placeholder at 0,0", id, port_id);
+        let mut p = Port::new(port_id, 0.0, 0.0);
+        p.containers.insert(id);
+        ports.insert(port_id, p);
+        port_index.invalidate();
+    }
+}
+
+// ids of a consignment's members, ascending
+fn consignment_members(container_store: &HashMap<usize, ContainerData>, consignment: usize) -> Vec<usize> {
+    let mut members: Vec<usize> = container_store.values().filter(|c| c.consignment() == Some(consignment)).map(|c| c.id()).collect();
+    members.sort_unstable();
+    members
+}
+
+// loads or unloads every member or none: the moves run on copies that replace the originals
+// only once all of them went through. returns the first member that failed
+fn move_consignment<F>(ship: &mut Ship, members: &[usize], ports: &mut HashMap<usize, Port>, container_store: &mut HashMap<usize, ContainerData>, step: F) -> Result<(), (usize, ShipOpError)>
+where
+    F: Fn(&mut Ship, usize, &mut HashMap<usize, Port>, &mut HashMap<usize, ContainerData>) -> Result<(), ShipOpError>,
+{
+    let mut trial_ship = ship.clone();
+    let mut trial_ports = ports.clone();
+    for &cid in members {
+        step(&mut trial_ship, cid, &mut trial_ports, container_store).map_err(|e| (cid, e))?;
+    }
+    *ship = trial_ship;
+    *ports = trial_ports;
+    Ok(())
+}
+
+// hands out ascending container ids above every id already in use
+pub struct IdAllocator {
+    next: usize,
+}
+
+impl IdAllocator {
+    pub fn after<I: IntoIterator<Item = usize>>(used: I) -> Self {
+        Self { next: used.into_iter().max().map_or(0, |m| m + 1) }
+    }
+}
+
+impl Iterator for IdAllocator {
+    type Item = usize;
+
+    fn next(&mut self) -> Option<usize> {
+        let id = self.next;
+        self.next += 1;
+        Some(id)
+    }
+}
+
+// fuel taken on at an automatic refuel stop (for simplicity a big fixed amount)
+const REFUEL_AMOUNT: f64 = 10000.0;
+
+// sails `ship` to `dest_port_id`; when the direct leg is unaffordable it looks for an
+// intermediate port to refuel at and continues from there.
+fn sail_with_refuel(ship: &mut Ship, dest_port_id: usize, ports: &mut HashMap<usize, Port>, port_index: &mut PortIndex, container_store: &HashMap<usize, ContainerData>, fuel_density: f64) -> Result<(), ShipOpError> {
+    match ship.sail_to(dest_port_id, ports, container_store, fuel_density) {
+        Err(ShipOpError::NotEnoughFuel) => {}
+        res => return res,
+    }
+    let current_port = ports.get(&ship.current_port).unwrap().clone();
+    let dest = ports.get(&dest_port_id).unwrap().clone();
+    // try refuel stops nearest-first and take the first one from which the destination is reachable
+    let candidates = port_index.nearest(ports, current_port.id, usize::MAX);
+    for &(pid, _) in candidates.iter() {
+        if pid == dest_port_id { continue; }
+        let stop = &ports[&pid];
+        if !stop.has_fuel { continue; }
+        let req_leg = ship.voyage_fuel_cost(&current_port, stop, container_store, fuel_density);
+        let onward = ship.voyage_fuel_cost(stop, &dest, container_store, fuel_density);
+        if ship.fuel < req_leg || (ship.fuel - req_leg + REFUEL_AMOUNT).min(ship.max_fuel_capacity) < onward || !stop.has_free_berth() {
+            continue;
+        }
+        ship.sail_to(pid, ports, container_store, fuel_density)?;
+        ship.re_fuel(REFUEL_AMOUNT);
+        return ship.sail_to(dest_port_id, ports, container_store, fuel_density);
+    }
+    // no stop gets us there: still pull into the nearest fuelling port to refuel if it's reachable
+    if let Some(&(npid, _)) = candidates.iter().find(|&&(pid, _)| pid != dest_port_id && ports[&pid].has_fuel) {
+        if ship.sail_to(npid, ports, container_store, fuel_density).is_ok() {
+            ship.re_fuel(REFUEL_AMOUNT);
+        }
+    }
+    Err(ShipOpError::NotEnoughFuel)
+}
+
+// berths opened up at `freed`: let waiting ships in, heaviest cargo first (ties by lowest id).
+// a ship leaving its own port to dock frees a berth there too, so the cascade keeps going.
+fn dock_waiting_ships(freed: usize, ports: &mut HashMap<usize, Port>, port_index: &mut PortIndex, ships: &mut HashMap<usize, Ship>, container_store: &HashMap<usize, ContainerData>, fuel_density: f64) {
+    let mut freed_ports = vec![freed];
+    while let Some(pid) = freed_ports.pop() {
+        loop {
+            let port = match ports.get_mut(&pid) {
+                Some(p) if p.has_free_berth() => p,
+                _ => break,
+            };
+            let next = port.waiting.iter().copied().max_by_key(|sid| {
+                let weight = ships.get(sid).map_or(0, |s| s.current_total_weight(container_store));
+                (weight, std::cmp::Reverse(*sid))
+            });
+            let sid = match next {
+                Some(sid) => sid,
+                None => break,
+            };
+            port.waiting.retain(|&w| w != sid);
+            let ship = match ships.get_mut(&sid) {
+                Some(s) => s,
+                None => continue,
+            };
+            let origin = ship.current_port;
+            match sail_with_refuel(ship, pid, ports, port_index, container_store, fuel_density) {
+                Ok(()) => log::info!("Ship {} left the queue and docked at port {}", sid, pid),
+                Err(e) => log::warn!("Ship {} dropped from the queue at port {}: {:?}", sid, pid, e),
+            }
+            // even a failed attempt may have moved the ship on to a refuel stop
+            if ship.current_port != origin {
+                freed_ports.push(origin);
+            }
+        }
+    }
+}
+
+// neighbour lists: for every port, all other ports nearest first (ties broken by lowest id).
+// built lazily: whoever adds or replaces ports only marks the index stale, and the next lookup
+// rebuilds it, so a run of CreatePort ops costs a single rebuild instead of one each.
+#[derive(Debug, Default)]
+pub struct PortIndex {
+    neighbors: HashMap<usize, Vec<(usize, f64)>>,
+    stale: bool,
+    rebuilds: usize, // how many times the lists were computed
+}
+
+impl PortIndex {
+    pub fn build(ports: &HashMap<usize, Port>) -> Self {
+        let mut index = Self::default();
+        index.rebuild(ports);
+        index
+    }
+
+    fn rebuild(&mut self, ports: &HashMap<usize, Port>) {
+        self.neighbors = ports.values().map(|from| {
+            let mut list: Vec<(usize, f64)> = ports.values().filter(|p| p.id != from.id).map(|p| (p.id, from.get_distance(p))).collect();
+            list.sort_by(|a, b| a.1.total_cmp(&b.1).then(a.0.cmp(&b.0)));
+            (from.id, list)
+        }).collect();
+        self.stale = false;
+        self.rebuilds += 1;
+    }
+
+    pub fn invalidate(&mut self) {
+        self.stale = true;
+    }
+
+    // up to `k` nearest other ports with their distances from `from_id`. rebuilds first when marked
+    // stale, or when the port count changed (placeholder ports appear without anyone marking it)
+    pub fn nearest(&mut self, ports: &HashMap<usize, Port>, from_id: usize, k: usize) -> &[(usize, f64)] {
+        if self.stale || self.neighbors.len() != ports.len() {
+            self.rebuild(ports);
+        }
+        match self.neighbors.get(&from_id) {
+            Some(list) => &list[..k.min(list.len())],
+            None => &[],
+        }
+    }
+}
+
+// evens out weight utilization among the in-service ships docked at `port_id`. greedy: the fullest
+// ship hands the container that best narrows its gap to the emptiest ship, via the yard, until no
+// move narrows it; every move goes through un_load/load so all limits hold. with `keep_consignments`
+// consignment members stay on their ship. returns the moves made
+fn rebalance(port_id: usize, keep_consignments: bool, ports: &mut HashMap<usize, Port>, ships: &mut HashMap<usize, Ship>, container_store: &mut HashMap<usize, ContainerData>) -> usize {
+    let mut fleet: Vec<usize> = match ports.get(&port_id) {
+        Some(p) => p.current.iter().copied().filter(|sid| ships.get(sid).is_some_and(|s| s.in_service && s.total_weight_capacity > 0)).collect(),
+        None => return 0,
+    };
+    fleet.sort_unstable();
+    let util = |s: &Ship, store: &HashMap<usize, ContainerData>| s.current_total_weight(store) as f64 / s.total_weight_capacity as f64;
+    let total_cargo: usize = fleet.iter().map(|sid| ships[sid].containers.len()).sum();
+    let mut moves = 0;
+    // each move strictly narrows the widest gap, the bound just keeps a pathological case finite
+    while moves < total_cargo * fleet.len() {
+        let by_util = |sid: &&usize| util(&ships[*sid], container_store);
+        let (full, empty) = match (fleet.iter().max_by(|a, b| by_util(a).total_cmp(&by_util(b))), fleet.iter().min_by(|a, b| by_util(a).total_cmp(&by_util(b)))) {
+            (Some(&f), Some(&e)) if f != e => (f, e),
+            _ => break,
+        };
+        let mut giver = ships.remove(&full).unwrap();
+        let mut taker = ships.remove(&empty).unwrap();
+        let gap = util(&giver, container_store) - util(&taker, container_store);
+        let (gw, tw) = (giver.current_total_weight(container_store) as f64, taker.current_total_weight(container_store) as f64);
+        let (gc, tc) = (giver.total_weight_capacity as f64, taker.total_weight_capacity as f64);
+        // candidates that narrow the gap, best first; a LIFO stack can only give its top
+        let offer: Vec<usize> = if giver.stacking == StackMode::Lifo { giver.containers.last().copied().into_iter().collect() } else { giver.containers.clone() };
+        let mut candidates: Vec<(f64, usize)> = offer.into_iter().filter_map(|cid| {
+            let cont = container_store.get(&cid)?;
+            if keep_consignments && cont.consignment().is_some() {
+                return None;
+            }
+            let w = cont.weight() as f64;
+            let new_gap = ((gw - w) / gc - (tw + w) / tc).abs();
+            if new_gap < gap { Some((new_gap, cid)) } else { None }
+        }).collect();
+        candidates.sort_by(|a, b| a.0.total_cmp(&b.0).then(a.1.cmp(&b.1)));
+        let mut moved = false;
+        for (_, cid) in candidates {
+            if giver.un_load(cid, ports, container_store).is_err() {
+                continue;
+            }
+            if taker.load(cid, ports, container_store).is_ok() {
+                moved = true;
+                break;
+            }
+            // the giver held it a moment ago, so taking it back can't fail
+            giver.load(cid, ports, container_store).unwrap();
+        }
+        ships.insert(full, giver);
+        ships.insert(empty, taker);
+        if !moved {
+            break;
+        }
+        moves += 1;
+    }
+    moves
+}
+
+// visits the assigned ports nearest-first, unloading each port's containers on arrival. with
+// `keep_consignments` a consignment is delivered only when all its members aboard are assigned to the same port.
+// returns (container_id, port_id, delivered) for every assignment in input order.
+fn distribute_cargo(ship: &mut Ship, assignments: &[(usize, usize)], keep_consignments: bool, ports: &mut HashMap<usize, Port>, port_index: &mut PortIndex, container_store: &mut HashMap<usize, ContainerData>, fuel_density: f64) -> Vec<(usize, usize, bool)> {
+    let mut delivered = vec![false; assignments.len()];
+    let mut pending: Vec<usize> = assignments.iter().map(|&(_, pid)| pid).collect();
+    pending.sort_unstable();
+    pending.dedup();
+    while !pending.is_empty() {
+        let here = ports.get(&ship.current_port).unwrap().clone();
+        // nearest remaining stop; unknown ports sort last and simply fail to sail
+        let (idx, _) = pending.iter().enumerate()
+            .map(|(i, pid)| (i, ports.get(pid).map(|p| here.get_distance(p)).unwrap_or(f64::MAX)))
+            .fold((0, f64::MAX), |best, cur| if cur.1 < best.1 { cur } else { best });
+        let pid = pending.remove(idx);
+        if pid != ship.current_port && sail_with_refuel(ship, pid, ports, port_index, container_store, fuel_density).is_err() {
+            continue;
+        }
+        for (i, &(cid, dest)) in assignments.iter().enumerate() {
+            if dest != pid || delivered[i] {
+                continue;
+            }
+            match container_store.get(&cid).and_then(|c| c.consignment()).filter(|_| keep_consignments && ship.containers.contains(&cid)) {
+                Some(c) => {
+                    // the first member reached unloads the whole consignment, provided each one is assigned here
+                    let members = ship.consignment_aboard(c, container_store);
+                    let slots: Option<Vec<usize>> = members.iter().map(|m| assignments.iter().position(|&(a, d)| a == *m && d == pid)).collect();
+                    if let Some(slots) = slots {
+                        if move_consignment(ship, &members, ports, container_store, |s, cid, p, cs| s.un_load(cid, p, cs)).is_ok() {
+                            for j in slots {
+                                delivered[j] = true;
+                            }
+                        }
+                    }
+                }
+                None => delivered[i] = ship.un_load(cid, ports, container_store).is_ok(),
+            }
+        }
+    }
+    assignments.iter().zip(delivered).map(|(&(cid, pid), ok)| (cid, pid, ok)).collect()
+}
+
+// output keys that sit next to the port labels
+const RESERVED_OUTPUT_KEYS: [&str; 2] = ["warnings", "weight_histogram"];
+
+// why `name` can't label port `port_id`, if it can't: labels key the output, so a name must not be a
+// reserved key, another port's "Port N" fallback, or the name of another port
+fn port_name_conflict(name: &str, port_id: usize, ports: &HashMap<usize, Port>) -> Option<String> {
+    if RESERVED_OUTPUT_KEYS.contains(&name) {
+        return Some(format!("\"{}\" is a reserved output key", name));
+    }
+    if name.strip_prefix("Port ").and_then(|n| n.parse::<usize>().ok()).is_some_and(|n| n != port_id) {
+        return Some(format!("\"{}\" is reserved for the port with that id", name));
+    }
+    ports
+        .values()
+        .find(|p| p.id != port_id && p.name.as_deref() == Some(name))
+        .map(|p| format!("\"{}\" already names port {}", name, p.id))
+}
+
+// one port of a CreatePorts batch; the fields mean the same as in CreatePort
+#[derive(Serialize, Deserialize)]
+struct PortSpec {
+    id: usize,
+    latitude: f64,
+    longitude: f64,
+    #[serde(default)]
+    max_berths: Option<usize>,
+    #[serde(default)]
+    name: Option<String>,
+    #[serde(default)]
+    has_fuel: Option<bool>,
+}
+
+impl PortSpec {
+    fn into_port(self) -> Port {
+        let mut p = Port::new(self.id, self.latitude, self.longitude);
+        p.name = self.name;
+        p.has_fuel = self.has_fuel.unwrap_or(p.has_fuel);
+        if let Some(n) = self.max_berths {
+            p.max_berths = n;
+        }
+        p
+    }
+}
+
+// container-count limits of a new ship
+#[derive(Serialize, Deserialize, Clone, Copy)]
+pub struct ShipLimits {
+    pub all: usize,
+    pub heavy: usize,
+    pub refrigerated: usize,
+    pub liquid: usize,
+}
+
+// CreateShip takes its limits either nested, `"limits": { "all": .., "heavy": .., .. }`,
+// or as the original flat max_number_of_* fields
+#[derive(Serialize, Deserialize)]
+#[serde(untagged, expecting = "ship limits: a \"limits\" object with all/heavy/refrigerated/liquid, or all four max_number_of_* fields")]
+enum ShipLimitsSpec {
+    Nested { limits: ShipLimits },
+    Flat { max_number_of_all_containers: usize, max_number_of_heavy_containers: usize, max_number_of_refrigerated_containers: usize, max_number_of_liquid_containers: usize },
+}
+
+impl ShipLimitsSpec {
+    fn resolve(&self) -> ShipLimits {
+        match *self {
+            ShipLimitsSpec::Nested { limits } => limits,
+            ShipLimitsSpec::Flat { max_number_of_all_containers, max_number_of_heavy_containers, max_number_of_refrigerated_containers, max_number_of_liquid_containers } => ShipLimits {
+                all: max_number_of_all_containers,
+                heavy: max_number_of_heavy_containers,
+                refrigerated: max_number_of_refrigerated_containers,
+                liquid: max_number_of_liquid_containers,
+            },
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum Operation {
+    CreatePort { id: usize, latitude: f64, longitude: f64, max_berths: Option<usize>, name: Option<String>, has_fuel: Option<bool> },
+    CreatePorts { ports: Vec<PortSpec> }, // all or nothing: a batch repeating an id or clashing on a name creates no port
+    CreateShip { id: usize, port_id: usize, total_weight_capacity: i32, #[serde(flatten)] limits: ShipLimitsSpec, fuel_consumption_per_km: f64, max_fuel_capacity: Option<f64>, stacking: Option<StackMode>, max_liquid_weight: Option<i32>, max_refrigerated_weight: Option<i32>, #[serde(default)] allowed_kinds: Option<Vec<String>>, #[serde(default)] wear_per_1000km: Option<f64>, #[serde(default)] upsert: bool },
+    CreateContainer { id: usize, weight: i32, special: Option<String>, port_id: usize, consumption_override: Option<f64>, value: Option<f64>, flat_consumption: Option<f64>, consignment: Option<usize> },
+    Load { ship_id: usize, container_id: usize },
+    CanLoad { ship_id: usize, container_id: usize }, // dry run of Load
+    Unload { ship_id: usize, container_id: usize },
+    Sail { ship_id: usize, dest_port_id: usize }, // waits in the destination's queue when every berth is taken
+    Refuel { ship_id: usize, amount: f64 },
+    Range { ship_id: usize },
+    DistributeCargo { ship_id: usize, assignments: Vec<(usize, usize)> }, // (container_id, dest_port_id)
+    Query { container_id: usize },
+    DecommissionShip { ship_id: usize },
+    RenamePort { port_id: usize, name: String },
+    AutoLoad { ship_id: usize, #[serde(default)] strategy: LoadStrategy },
+    OffloadAll { ship_id: usize },
+    Reweigh { container_id: usize, weight: i32 },
+    CloneShip { source_id: usize, new_id: usize, port_id: usize },
+    SetRouteWeather { from_port: usize, to_port: usize, multiplier: f64 }, // applies to the directed leg only
+    TransferFuel { from_ship: usize, to_ship: usize, amount: f64 },
+    Transfer { from_ship: usize, to_ship: usize, container_id: usize }, // between ships at the same port
+    Bounds,
+    ShipsCarrying { kind: String },
+    SetShipService { ship_id: usize, in_service: bool },
+    EvacuateToFuel, // every ship heads for the nearest port with fuel it can reach
+    Undo, // reverts the most recent state-changing operation
+    FindContainers { kind: Option<String>, min_weight: Option<i32>, max_weight: Option<i32> }, // weight bounds are inclusive
+    CountContainers,
+    ClearHistory { port_id: usize }, // forgets past visits; docked ships stay docked
+    CreateContainerAuto { weight: i32, special: Option<String>, port_id: usize }, // id is the next one not in use
+    LoadConsignment { ship_id: usize, consignment: usize }, // every member or none
+    UnloadConsignment { ship_id: usize, consignment: usize },
+    OptimalDepot,
+    DistanceMatrix { path: Option<String> }, // CSV file to write; printed when absent
+    EmptyPorts,
+    ClosestReachable { ship_id: usize },
+    Checkpoint { label: String }, // saves the world under `label` for --diff; a repeated label replaces the earlier one
+    Rebalance { port_id: usize },
+    Maintain { ship_id: usize }, // clears the wear built up since the last maintenance
+}
+
+impl Operation {
+    // operation names as written in messages; the input keys are their lowercase forms
+    const NAMES: [&'static str; 39] = ["CreatePort", "CreateShip", "CreateContainer", "Load", "Unload", "Sail", "Refuel", "Range", "DistributeCargo", "Query", "DecommissionShip", "RenamePort", "AutoLoad", "OffloadAll", "Reweigh", "CloneShip", "FindContainers", "SetRouteWeather", "TransferFuel", "Bounds", "Transfer", "Undo", "ShipsCarrying", "SetShipService", "EvacuateToFuel", "CanLoad", "CountContainers", "ClearHistory", "CreateContainerAuto", "CreatePorts", "LoadConsignment", "UnloadConsignment", "OptimalDepot", "DistanceMatrix", "Rebalance", "Maintain", "EmptyPorts", "ClosestReachable", "Checkpoint"];
+
+    // the operation name for an input key such as "createport"
+    fn name_for_key(key: &str) -> Option<&'static str> {
+        Self::NAMES.iter().copied().find(|name| name.eq_ignore_ascii_case(key))
+    }
+
+    // variant name, used for per-kind reporting
+    fn kind(&self) -> &'static str {
+        match self {
+            Operation::CreatePort { .. } => "CreatePort",
+            Operation::CreatePorts { .. } => "CreatePorts",
+            Operation::CreateShip { .. } => "CreateShip",
+            Operation::CreateContainer { .. } => "CreateContainer",
+            Operation::Load { .. } => "Load",
+            Operation::CanLoad { .. } => "CanLoad",
+            Operation::Unload { .. } => "Unload",
+            Operation::Sail { .. } => "Sail",
+            Operation::Refuel { .. } => "Refuel",
+            Operation::Range { .. } => "Range",
+            Operation::DistributeCargo { .. } => "DistributeCargo",
+            Operation::Query { .. } => "Query",
+            Operation::DecommissionShip { .. } => "DecommissionShip",
+            Operation::RenamePort { .. } => "RenamePort",
+            Operation::AutoLoad { .. } => "AutoLoad",
+            Operation::OffloadAll { .. } => "OffloadAll",
+            Operation::Reweigh { .. } => "Reweigh",
+            Operation::CloneShip { .. } => "CloneShip",
+            Operation::FindContainers { .. } => "FindContainers",
+            Operation::TransferFuel { .. } => "TransferFuel",
+            Operation::Transfer { .. } => "Transfer",
+            Operation::Bounds => "Bounds",
+            Operation::ShipsCarrying { .. } => "ShipsCarrying",
+            Operation::SetShipService { .. } => "SetShipService",
+            Operation::EvacuateToFuel => "EvacuateToFuel",
+            Operation::SetRouteWeather { .. } => "SetRouteWeather",
+            Operation::Undo => "Undo",
+            Operation::CountContainers => "CountContainers",
+            Operation::ClearHistory { .. } => "ClearHistory",
+            Operation::CreateContainerAuto { .. } => "CreateContainerAuto",
+            Operation::LoadConsignment { .. } => "LoadConsignment",
+            Operation::UnloadConsignment { .. } => "UnloadConsignment",
+            Operation::OptimalDepot => "OptimalDepot",
+            Operation::DistanceMatrix { .. } => "DistanceMatrix",
+            Operation::EmptyPorts => "EmptyPorts",
+            Operation::ClosestReachable { .. } => "ClosestReachable",
+            Operation::Checkpoint { .. } => "Checkpoint",
+            Operation::Rebalance { .. } => "Rebalance",
+            Operation::Maintain { .. } => "Maintain",
+        }
+    }
+
+    // whether the operation can change the world, and so leaves a snapshot for Undo
+    fn is_undoable(&self) -> bool {
+        !matches!(self, Operation::Range { .. } | Operation::Query { .. } | Operation::CanLoad { .. } | Operation::FindContainers { .. } | Operation::Bounds | Operation::ShipsCarrying { .. } | Operation::Undo | Operation::CountContainers | Operation::OptimalDepot | Operation::DistanceMatrix { .. } | Operation::EmptyPorts | Operation::ClosestReachable { .. } | Operation::Checkpoint { .. })
+    }
+}
+
+// full copy of the world taken before an undoable operation
+struct Snapshot {
+    ports: HashMap<usize, Port>,
+    ships: HashMap<usize, Ship>,
+    container_store: HashMap<usize, ContainerData>,
+}
+
+// how many operations back Undo can reach; older snapshots are dropped
+const UNDO_DEPTH: usize = 32;
+
+// what changed between two snapshots; every list is ascending by id
+#[derive(Debug, Default)]
+struct SnapshotDiff {
+    ships_added: Vec<usize>,
+    ships_removed: Vec<usize>,
+    ships_moved: Vec<(usize, usize, usize)>,                          // (ship, from port, to port)
+    fuel_deltas: Vec<(usize, f64)>,                                   // (ship, after - before)
+    containers_relocated: Vec<(usize, Option<Location>, Option<Location>)>, // None where the container did not exist
+}
+
+impl SnapshotDiff {
+    fn is_empty(&self) -> bool {
+        self.ships_added.is_empty() && self.ships_removed.is_empty() && self.ships_moved.is_empty() && self.fuel_deltas.is_empty() && self.containers_relocated.is_empty()
+    }
+}
+
+fn diff_snapshots(before: &Snapshot, after: &Snapshot) -> SnapshotDiff {
+    let mut diff = SnapshotDiff::default();
+    let mut ship_ids: Vec<usize> = before.ships.keys().chain(after.ships.keys()).copied().collect::<HashSet<_>>().into_iter().collect();
+    ship_ids.sort_unstable();
+    for sid in ship_ids {
+        match (before.ships.get(&sid), after.ships.get(&sid)) {
+            (Some(a), Some(b)) => {
+                if a.current_port != b.current_port {
+                    diff.ships_moved.push((sid, a.current_port, b.current_port));
+                }
+                if (b.fuel - a.fuel).abs() > 1e-9 {
+                    diff.fuel_deltas.push((sid, b.fuel - a.fuel));
+                }
+            }
+            (None, Some(_)) => diff.ships_added.push(sid),
+            (Some(_), None) => diff.ships_removed.push(sid),
+            (None, None) => {}
+        }
+    }
+    let mut container_ids: Vec<usize> = before.container_store.keys().chain(after.container_store.keys()).copied().collect::<HashSet<_>>().into_iter().collect();
+    container_ids.sort_unstable();
+    for cid in container_ids {
+        let was = locate_container(cid, &before.ports, &before.ships, &before.container_store);
+        let now = locate_container(cid, &after.ports, &after.ships, &after.container_store);
+        if was != now {
+            diff.containers_relocated.push((cid, was, now));
+        }
+    }
+    diff
+}
+
+#[derive(Serialize, Deserialize)]
+struct InputFile {
+    // `{}` is a valid, empty scenario
+    #[serde(default)]
+    operations: Vec<Operation>,
+    // ascending bucket bounds; when present the output gets a "weight_histogram" entry
+    #[serde(default)]
+    weight_histogram: Option<Vec<i32>>,
+    // scales all fuel use in the scenario
+    #[serde(default = "nominal_fuel_density")]
+    fuel_density: f64,
+    // order of the container id lists in the output
+    #[serde(default)]
+    output_sort: OutputSort,
+    // label put next to every fuel figure in the output
+    #[serde(default = "default_fuel_unit")]
+    fuel_unit: String,
+    // when set, consignment members can only move as a group (LoadConsignment / UnloadConsignment);
+    // AutoLoad, OffloadAll, DecommissionShip, DistributeCargo, Transfer and Rebalance keep them together too
+    #[serde(default)]
+    keep_consignments: bool,
+    // how Sail charges the distance part of its fuel to the cargo (see allocate_voyage_cost)
+    #[serde(default)]
+    base_fuel_split: BaseFuelSplit,
+}
+
+// the same settings an input file gets for every key it leaves out
+impl Default for InputFile {
+    fn default() -> Self {
+        Self { operations: Vec::new(), weight_histogram: None, fuel_density: nominal_fuel_density(), output_sort: OutputSort::default(), fuel_unit: default_fuel_unit(), keep_consignments: false, base_fuel_split: BaseFuelSplit::default() }
+    }
+}
+
+impl InputFile {
+    // appends `part`'s operations; a setting `part` gives replaces the current one
+    fn merge(&mut self, part: InputFile) {
+        self.operations.extend(part.operations);
+        if part.weight_histogram.is_some() {
+            self.weight_histogram = part.weight_histogram;
+        }
+        if part.fuel_density != nominal_fuel_density() {
+            self.fuel_density = part.fuel_density;
+        }
+        if part.output_sort != OutputSort::default() {
+            self.output_sort = part.output_sort;
+        }
+        if part.fuel_unit != default_fuel_unit() {
+            self.fuel_unit = part.fuel_unit;
+        }
+        if part.keep_consignments {
+            self.keep_consignments = true;
+        }
+        if part.base_fuel_split != BaseFuelSplit::default() {
+            self.base_fuel_split = part.base_fuel_split;
+        }
+    }
+}
+
+fn nominal_fuel_density() -> f64 {
+    1.0
+}
+
+fn default_fuel_unit() -> String {
+    "L".to_string()
+}
+
+// `buckets` are ascending upper bounds; anything at or above the last one lands in a trailing "N+" bucket
+fn weight_histogram(container_store: &HashMap<usize, ContainerData>, buckets: &[i32]) -> Vec<(String, usize)> {
+    let mut counts = vec![0; buckets.len() + 1];
+    for cont in container_store.values() {
+        let idx = buckets.iter().position(|&b| cont.weight() < b).unwrap_or(buckets.len());
+        counts[idx] += 1;
+    }
+    let mut out = Vec::new();
+    let mut lower = 0;
+    for (&upper, &count) in buckets.iter().zip(counts.iter()) {
+        out.push((format!("{}-{}", lower, upper), count));
+        lower = upper;
+    }
+    out.push((format!("{}+", lower), counts[buckets.len()]));
+    out
+}
+
+// re-parses the operations one at a time to point at the entry that broke the top-level parse;
+// `value` is the whole input, already read from whichever format it was written in
+fn explain_parse_error(value: &serde_json::Value) -> Option<String> {
+    let ops = value.get("operations")?.as_array()?;
+    for (i, op) in ops.iter().enumerate() {
+        if let Err(e) = serde_json::from_value::<Operation>(op.clone()) {
+            let key = op.as_object().and_then(|o| o.keys().next()).map(String::as_str);
+            let kind = key.and_then(Operation::name_for_key).or(key).unwrap_or("operation");
+            let msg = e.to_string();
+            return Some(match msg.strip_prefix("missing field `").and_then(|m| m.split('`').next()) {
+                Some(field) => format!("{} at index {} missing '{}'", kind, i, field),
+                None => format!("{} at index {}: {}", kind, i, msg),
+            });
+        }
+    }
+    None
+}
+
+// decimals kept for coordinates and fuel figures in the output
+const OUTPUT_DECIMALS: usize = 2;
+
+// rounds through a fixed-precision string so the output never carries float noise
+// like 48.86000000000001, and folds -0.0 into 0.0
+fn output_number(x: f64) -> f64 {
+    let rounded: f64 = format!("{:.*}", OUTPUT_DECIMALS, x).parse().unwrap();
+    if rounded == 0.0 { 0.0 } else { rounded }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputSort {
+    #[default]
+    ById,
+    ByWeightThenId,
+}
+
+// sorts an output id list; the weight order is a stable sort so equal keys keep their place.
+// ids missing from the store sort as weight 0
+fn sort_output_ids(ids: &mut [usize], order: OutputSort, container_store: &HashMap<usize, ContainerData>) {
+    match order {
+        OutputSort::ById => ids.sort_unstable(),
+        OutputSort::ByWeightThenId => {
+            ids.sort_by_key(|cid| (container_store.get(cid).map_or(0, |c| c.weight()), *cid))
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct OutputPort {
+    lat: f64,
+    lon: f64,
+    basic_container: Vec<usize>,
+    heavy_container: Vec<usize>,
+    refrigerated_container: Vec<usize>,
+    liquid_container: Vec<usize>,
+    ships: HashMap<String, OutputShip>,
+}
+
+#[derive(Serialize)]
+struct OutputShip {
+    fuel_left: f64,
+    basic_container: Vec<usize>,
+    heavy_container: Vec<usize>,
+    refrigerated_container: Vec<usize>,
+    liquid_container: Vec<usize>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum InputFormat {
+    Json,
+    Json5, // comments, trailing commas
+    // operations as an array of tables, one sub-table per operation named like its JSON key:
+    //   [[operations]]
+    //   createport = { id = 0, latitude = 0.0, longitude = 0.0 }
+    //   [[operations]]
+    //   countcontainers = {}
+    // unit operations take an empty table since an array of tables cannot hold bare strings
+    Toml,
+}
+
+impl InputFormat {
+    // by extension; anything unrecognised is read as JSON, or JSON5 under --json5
+    fn for_path(path: &str, json5: bool) -> Self {
+        if path.ends_with(".toml") {
+            InputFormat::Toml
+        } else if json5 || path.ends_with(".json5") {
+            InputFormat::Json5
+        } else {
+            InputFormat::Json
+        }
+    }
+}
+
+// a TOML scenario as the JSON value it stands for, so its errors can be explained like JSON ones:
+// an empty table naming a unit operation becomes the bare string JSON uses for it
+fn toml_as_json(input_text: &str) -> Option<serde_json::Value> {
+    let mut value: serde_json::Value = toml::from_str(input_text).ok()?;
+    for op in value.get_mut("operations").and_then(|o| o.as_array_mut()).into_iter().flatten() {
+        let unit = op.as_object()
+            .filter(|o| o.len() == 1 && o.values().all(|v| v.as_object().is_some_and(|t| t.is_empty())))
+            .and_then(|o| o.keys().next())
+            .map(|k| serde_json::Value::String(k.clone()))
+            .filter(|name| serde_json::from_value::<Operation>(name.clone()).is_ok());
+        if let Some(name) = unit {
+            *op = name;
+        }
+    }
+    Some(value)
+}
+
+// strict JSON by default; JSON5 or TOML when asked for
+fn parse_input(input_text: &str, format: InputFormat) -> Result<InputFile, String> {
+    // the broken operation when the text itself is well-formed, the parser's own message otherwise
+    let explain = |e: String, value: Option<serde_json::Value>| value.as_ref().and_then(explain_parse_error).unwrap_or(e);
+    match format {
+        InputFormat::Json5 => json5::from_str(input_text).map_err(|e| format!("invalid JSON5: {}", explain(e.to_string(), json5::from_str(input_text).ok()))),
+        InputFormat::Toml => toml::from_str(input_text).map_err(|e| format!("invalid TOML: {}", explain(e.to_string(), toml_as_json(input_text)))),
+        InputFormat::Json => serde_json::from_str(input_text).map_err(|e| format!("invalid JSON: {}", explain(e.to_string(), serde_json::from_str(input_text).ok()))),
+    }
+}
+
+// what a run leaves behind: the final world
+struct RunState {
+    ports: HashMap<usize, Port>,
+    ships: HashMap<usize, Ship>,
+    container_store: HashMap<usize, ContainerData>,
+    // problems noticed during the run or while building the output; reported instead of panicking
+    warnings: Vec<String>,
+    port_index: PortIndex,
+    // cumulative wall time and count per operation kind, filled when `timing` is set
+    timings: BTreeMap<&'static str, (Duration, usize)>,
+    summary: Summary,
+    checkpoints: HashMap<String, Snapshot>, // world states saved by Checkpoint, by label
+}
+
+// runs the input's operations on an empty world
+fn run(input: &mut InputFile, fail_fast: bool, timing: bool) -> Result<RunState, String> {
+    let fuel_density = input.fuel_density;
+    let keep_consignments = input.keep_consignments;
+    let base_fuel_split = input.base_fuel_split;
+    if input.operations.is_empty() {
+        log::info!("No operations to run; writing an empty world");
+    }
+
+    let mut ports: HashMap<usize, Port> = HashMap::new();
+    let mut ships: HashMap<usize, Ship> = HashMap::new();
+    let mut container_store: HashMap<usize, ContainerData> = HashMap::new();
+
+    // problems noticed during the run or while building the output; reported instead of panicking
+    let mut warnings: Vec<String> = Vec::new();
+    // nearest-port lookups for the refuel fallback; rebuilt on first use after ports are added or replaced
+    let mut port_index = PortIndex::default();
+    // world states before the most recent undoable operations, newest last
+    let mut undo_stack: VecDeque<Snapshot> = VecDeque::new();
+    // world states saved by Checkpoint, compared with --diff
+    let mut checkpoints: HashMap<String, Snapshot> = HashMap::new();
+    let mut run_summary = Summary::default();
+    // cumulative wall time and count per operation kind, filled when `timing` is set
+    let mut timings: BTreeMap<&'static str, (Duration, usize)> = BTreeMap::new();
+    // cloning the world before every operation only pays off when something can undo it
+    let keep_undo = input.operations.iter().any(|op| matches!(op, Operation::Undo));
+
+    for (index, op) in std::mem::take(&mut input.operations).into_iter().enumerate() {
+        let kind = op.kind();
+        let started = Instant::now();
+        // snapshots are kept whether or not the operation succeeds, so Undo always steps back exactly one
+        if keep_undo && op.is_undoable() {
+            if undo_stack.len() == UNDO_DEPTH {
+                undo_stack.pop_front();
+            }
+            undo_stack.push_back(Snapshot { ports: ports.clone(), ships: ships.clone(), container_store: container_store.clone() });
+        }
+        // Err carries a description of why the operation was skipped
+        let outcome: Result<(), String> = match op {
+            Operation::CreatePort { id, latitude, longitude, max_berths, name, has_fuel } => {
+                match name.as_deref().and_then(|n| port_name_conflict(n, id, &ports)) {
+                    Some(conflict) => Err(format!("Cannot create port {}: {}", id, conflict)),
+                    None => {
+                        ports.insert(id, PortSpec { id, latitude, longitude, max_berths, name, has_fuel }.into_port());
+                        port_index.invalidate();
+                        Ok(())
+                    }
+                }
+            }
+            Operation::CreatePorts { ports: specs } => {
+                let mut seen = HashSet::new();
+                let mut duplicates: Vec<usize> = specs.iter().map(|spec| spec.id).filter(|&id| !seen.insert(id)).collect();
+                duplicates.sort_unstable();
+                duplicates.dedup();
+                let mut seen_names = HashSet::new();
+                let name_conflict = specs.iter().find_map(|spec| {
+                    let name = spec.name.as_deref()?;
+                    if !seen_names.insert(name) {
+                        return Some(format!("\"{}\" appears more than once in the batch", name));
+                    }
+                    port_name_conflict(name, spec.id, &ports).map(|conflict| format!("port {}: {}", spec.id, conflict))
+                });
+                if !duplicates.is_empty() {
+                    Err(format!("Cannot create ports: ids {:?} appear more than once in the batch", duplicates))
+                } else if let Some(conflict) = name_conflict {
+                    Err(format!("Cannot create ports: {}", conflict))
+                } else {
+                    for spec in specs {
+                        ports.insert(spec.id, spec.into_port());
+                    }
+                    port_index.invalidate();
+                    Ok(())
+                }
+            }
+            Operation::CreateShip { id, port_id, total_weight_capacity, limits, fuel_consumption_per_km, max_fuel_capacity, stacking, max_liquid_weight, max_refrigerated_weight, allowed_kinds, wear_per_1000km, upsert } => {
+                let ShipLimits { all: max_number_of_all_containers, heavy: max_number_of_heavy_containers, refrigerated: max_number_of_refrigerated_containers, liquid: max_number_of_liquid_containers } = limits.resolve();
+                let unknown_kind = allowed_kinds.iter().flatten().find(|k| !ContainerData::KINDS.contains(&k.as_str())).cloned();
+                if !(fuel_consumption_per_km.is_finite() && fuel_consumption_per_km > 0.0) {
+                    Err(format!("Cannot create ship {}: fuel_consumption_per_km must be positive, got {}", id, fuel_consumption_per_km))
+                } else if wear_per_1000km.is_some_and(|w| !(w.is_finite() && w >= 0.0)) {
+                    Err(format!("Cannot create ship {}: wear_per_1000km must not be negative, got {}", id, wear_per_1000km.unwrap()))
+                } else if !(ports.contains_key(&port_id) || upsert && ships.contains_key(&id)) {
+                    // a ship placed nowhere would break every later voyage from its current_port;
+                    // an upsert of an existing ship keeps its location, so the port is not needed
+                    Err(format!("Cannot create ship {}: unknown port {}", id, port_id))
+                } else if let Some(k) = unknown_kind {
+                    Err(format!("Cannot create ship {}: unknown container kind {:?}; expected one of {:?}", id, k, ContainerData::KINDS))
+                } else {
+                    // upsert only retunes an existing ship; its cargo, fuel and location stay as they are
+                    if let Some(s) = ships.get_mut(&id).filter(|_| upsert) {
+                        s.total_weight_capacity = total_weight_capacity;
+                        s.max_number_of_all_containers = max_number_of_all_containers;
+                        s.max_number_of_heavy_containers = max_number_of_heavy_containers;
+                        s.max_number_of_refrigerated_containers = max_number_of_refrigerated_containers;
+                        s.max_number_of_liquid_containers = max_number_of_liquid_containers;
+                        s.fuel_consumption_per_km = fuel_consumption_per_km;
+                        s.stacking = stacking.unwrap_or(s.stacking);
+                        if let Some(cap) = max_fuel_capacity {
+                            s.max_fuel_capacity = cap;
+                            s.fuel = s.fuel.min(cap);
+                        }
+                        s.max_liquid_weight = max_liquid_weight.unwrap_or(s.max_liquid_weight);
+                        s.max_refrigerated_weight = max_refrigerated_weight.unwrap_or(s.max_refrigerated_weight);
+                        if allowed_kinds.is_some() {
+                            s.allowed_kinds = allowed_kinds;
+                        }
+                        s.wear_per_1000km = wear_per_1000km.unwrap_or(s.wear_per_1000km);
+                        if port_id != s.current_port {
+                            log::info!("Upsert of ship {} keeps it at port {} (ignoring port {})", id, s.current_port, port_id);
+                        }
+                    } else {
+                        let mut s = Ship::new(id, port_id, total_weight_capacity, max_number_of_all_containers, max_number_of_heavy_containers, max_number_of_refrigerated_containers, max_number_of_liquid_containers, fuel_consumption_per_km);
+                        s.stacking = stacking.unwrap_or_default();
+                        if let Some(cap) = max_fuel_capacity {
+                            s.max_fuel_capacity = cap;
+                        }
+                        s.max_liquid_weight = max_liquid_weight.unwrap_or(s.max_liquid_weight);
+                        s.max_refrigerated_weight = max_refrigerated_weight.unwrap_or(s.max_refrigerated_weight);
+                        s.allowed_kinds = allowed_kinds;
+                        s.wear_per_1000km = wear_per_1000km.unwrap_or(0.0);
+                        ports.get_mut(&port_id).unwrap().incoming_ship(id);
+                        ships.insert(id, s);
+                    }
+                    Ok(())
+                }
+            }
+            Operation::CloneShip { source_id, new_id, port_id } => {
+                if ships.contains_key(&new_id) {
+                    Err(format!("Cannot clone ship {} as {}: id {} is taken", source_id, new_id, new_id))
+                } else if !ports.contains_key(&port_id) {
+                    Err(format!("Cannot clone ship {} as {}: unknown port {}", source_id, new_id, port_id))
+                } else if let Some(source) = ships.get(&source_id) {
+                    let s = source.clone_as(new_id, port_id);
+                    ports.get_mut(&port_id).unwrap().incoming_ship(new_id);
+                    ships.insert(new_id, s);
+                    Ok(())
+                } else {
+                    Err(format!("Cannot clone unknown ship {}", source_id))
+                }
+            }
+            Operation::CreateContainer { id, weight, special, port_id, consumption_override, value, flat_consumption, consignment } => {
+                let extras = ContainerExtras { consumption_override, value, flat_consumption: flat_consumption.unwrap_or(0.0), consignment };
+                container_store.insert(id, build_container(id, weight, special.as_deref(), extras));
+                place_container(id, port_id, &mut ports, &mut port_index);
+                Ok(())
+            }
+            Operation::CreateContainerAuto { weight, special, port_id } => {
+                let used = container_store.keys().chain(ports.values().flat_map(|p| p.containers.iter())).chain(ships.values().flat_map(|s| s.containers.iter()));
+                let id = IdAllocator::after(used.cloned()).next().unwrap();
+                container_store.insert(id, build_container(id, weight, special.as_deref(), ContainerExtras::default()));
+                place_container(id, port_id, &mut ports, &mut port_index);
+                log::info!("Created container {} at port {}", id, port_id);
+                Ok(())
+            }
+            Operation::Load { ship_id, container_id } => {
+                let consignment = container_store.get(&container_id).and_then(|c| c.consignment()).filter(|_| keep_consignments);
+                match (consignment, ships.get_mut(&ship_id)) {
+                    (Some(c), _) => Err(format!("Container {} belongs to consignment {}; load it with LoadConsignment", container_id, c)),
+                    (None, Some(ship)) => ship.load(container_id, &mut ports, &mut container_store)
+                        .map_err(|e| format!("Ship {} could not load container {}: {:?}", ship_id, container_id, e)),
+                    (None, None) => Err(format!("Cannot load onto unknown ship {}", ship_id)),
+                }
+            }
+            Operation::LoadConsignment { ship_id, consignment } => {
+                let members = consignment_members(&container_store, consignment);
+                match ships.get_mut(&ship_id) {
+                    _ if members.is_empty() => Err(format!("Consignment {} has no containers", consignment)),
+                    Some(ship) => move_consignment(ship, &members, &mut ports, &mut container_store, |s, cid, p, cs| s.load(cid, p, cs))
+                        .map_err(|(cid, e)| format!("Ship {} could not load consignment {}: container {}: {:?}", ship_id, consignment, cid, e)),
+                    None => Err(format!("Cannot load onto unknown ship {}", ship_id)),
+                }
+            }
+            Operation::UnloadConsignment { ship_id, consignment } => {
+                match ships.get_mut(&ship_id) {
+                    Some(ship) => {
+                        let members = ship.consignment_aboard(consignment, &container_store);
+                        if members.is_empty() {
+                            Err(format!("Ship {} carries nothing of consignment {}", ship_id, consignment))
+                        } else {
+                            move_consignment(ship, &members, &mut ports, &mut container_store, |s, cid, p, cs| s.un_load(cid, p, cs))
+                                .map_err(|(cid, e)| format!("Ship {} could not unload consignment {}: container {}: {:?}", ship_id, consignment, cid, e))
+                        }
+                    }
+                    None => Err(format!("Cannot unload from unknown ship {}", ship_id)),
+                }
+            }
+            Operation::CanLoad { ship_id, container_id } => {
+                match ships.get(&ship_id) {
+                    Some(ship) => {
+                        match ship.can_load(container_id, &ports, &container_store) {
+                            Ok(()) => println!("Ship {} can load container {}: true", ship_id, container_id),
+                            Err(e) => println!("Ship {} can load container {}: false ({:?})", ship_id, container_id, e),
+                        }
+                        Ok(())
+                    }
+                    None => Err(format!("Cannot check loading onto unknown ship {}", ship_id)),
+                }
+            }
+            Operation::Unload { ship_id, container_id } => {
+                let consignment = container_store.get(&container_id).and_then(|c| c.consignment()).filter(|_| keep_consignments);
+                match (consignment, ships.get_mut(&ship_id)) {
+                    (Some(c), _) => Err(format!("Container {} belongs to consignment {}; unload it with UnloadConsignment", container_id, c)),
+                    (None, Some(ship)) => ship.un_load(container_id, &mut ports, &mut container_store)
+                        .map_err(|e| format!("Ship {} could not unload container {}: {:?}", ship_id, container_id, e)),
+                    (None, None) => Err(format!("Cannot unload from unknown ship {}", ship_id)),
+                }
+            }
+            Operation::Refuel { ship_id, amount } => {
+                match ships.get_mut(&ship_id) {
+                    Some(ship) if ports.get(&ship.current_port).is_some_and(|p| !p.has_fuel) => {
+                        Err(format!("Ship {} cannot refuel at port {}: {:?}", ship_id, ship.current_port, ShipOpError::NoFuelAtPort))
+                    }
+                    Some(ship) => {
+                        ship.re_fuel(amount);
+                        Ok(())
+                    }
+                    None => Err(format!("Cannot refuel unknown ship {}", ship_id)),
+                }
+            }
+            Operation::Sail { ship_id, dest_port_id } => {
+                if let Some(ship) = ships.get_mut(&ship_id) {
+                    let origin = ship.current_port;
+                    let (burned, sailed) = (ship.fuel_burned, ship.km_sailed);
+                    let result = match sail_with_refuel(ship, dest_port_id, &mut ports, &mut port_index, &container_store, fuel_density) {
+                        Ok(()) => {
+                            // a new voyage supersedes any queue the ship was waiting in
+                            for p in ports.values_mut() {
+                                p.waiting.retain(|&w| w != ship_id);
+                            }
+                            Ok(())
+                        }
+                        Err(ShipOpError::AlreadyThere) => {
+                            log::info!("Ship {} is already at port {}", ship_id, dest_port_id);
+                            Ok(())
+                        }
+                        // sail_to still reports a full port as NoBerthAvailable; the operation turns that into a
+                        // place in the port's queue rather than a failure, and the ship docks once a berth frees
+                        Err(ShipOpError::NoBerthAvailable) => {
+                            log::info!("Ship {} queued for a berth at port {}", ship_id, dest_port_id);
+                            let waiting = &mut ports.get_mut(&dest_port_id).unwrap().waiting;
+                            if !waiting.contains(&ship_id) {
+                                waiting.push(ship_id);
+                            }
+                            Ok(())
+                        }
+                        Err(e) => Err(format!("Ship {} could not sail to port {}: {:?}", ship_id, dest_port_id, e)),
+                    };
+                    // the cargo pays for whatever was burned, a refuel stop or a leg short of the destination included
+                    let ship = &ships[&ship_id];
+                    if ship.fuel_burned > burned {
+                        for (cid, fuel) in allocate_voyage_cost(ship, ship.fuel_burned - burned, ship.km_sailed - sailed, &container_store, base_fuel_split) {
+                            *run_summary.container_fuel.entry(cid).or_default() += fuel;
+                        }
+                    }
+                    // a failed voyage may still have left the origin (stuck at a refuel stop), freeing its berth all the same
+                    if ships[&ship_id].current_port != origin {
+                        dock_waiting_ships(origin, &mut ports, &mut port_index, &mut ships, &container_store, fuel_density);
+                    }
+                    result
+                } else {
+                    Err(format!("Cannot sail unknown ship {}", ship_id))
+                }
+            }
+            Operation::DistributeCargo { ship_id, assignments } => {
+                if let Some(ship) = ships.get_mut(&ship_id) {
+                    let origin = ship.current_port;
+                    let mut failed = Vec::new();
+                    for (cid, pid, delivered) in distribute_cargo(ship, &assignments, keep_consignments, &mut ports, &mut port_index, &mut container_store, fuel_density) {
+                        println!("Ship {}: container {} -> port {}: {}", ship_id, cid, pid, if delivered { "delivered" } else { "failed" });
+                        if !delivered {
+                            failed.push(cid);
+                        }
+                    }
+                    if ship.current_port != origin {
+                        dock_waiting_ships(origin, &mut ports, &mut port_index, &mut ships, &container_store, fuel_density);
+                    }
+                    if failed.is_empty() { Ok(()) } else { Err(format!("Ship {} failed to deliver containers {:?}", ship_id, failed)) }
+                } else {
+                    Err(format!("Cannot distribute cargo of unknown ship {}", ship_id))
+                }
+            }
+            Operation::Query { container_id } => {
+                match locate_container(container_id, &ports, &ships, &container_store) {
+                    Some(Location::Port(pid)) => println!("Container {} is at {}", container_id, ports[&pid].label()),
+                    Some(loc) => println!("Container {} is at {:?}", container_id, loc),
+                    None => println!("Container {} does not exist", container_id),
+                }
+                Ok(())
+            }
+            Operation::DecommissionShip { ship_id } => {
+                // cargo goes ashore at the ship's current port, then the ship is gone
+                match ships.get_mut(&ship_id) {
+                    // its cargo can't come off in maintenance, so refuse before touching anything
+                    Some(ship) if !ship.in_service => {
+                        Err(format!("Ship {} could not be decommissioned: {:?}", ship_id, ShipOpError::ShipOutOfService))
+                    }
+                    Some(ship) => {
+                        let offloaded = ship.offload_all(keep_consignments, &mut ports, &mut container_store);
+                        if !ship.containers.is_empty() {
+                            // reload what already came off, bottom of the stack first, so the failed op leaves no trace;
+                            // each container fitted before it came off, so it fits again
+                            for &cid in offloaded.iter().rev() {
+                                ship.load(cid, &mut ports, &mut container_store).unwrap();
+                            }
+                            // the round trip counted each container both ways; take that back out of the flow
+                            let port = ports.get_mut(&ship.current_port).unwrap();
+                            port.arrived -= offloaded.len();
+                            port.departed -= offloaded.len();
+                            Err(format!("Ship {} could not be decommissioned: cargo {:?} did not come off", ship_id, ship.containers))
+                        } else {
+                            let port_id = ship.current_port;
+                            ships.remove(&ship_id);
+                            for p in ports.values_mut() {
+                                p.waiting.retain(|&w| w != ship_id);
+                            }
+                            if let Some(p) = ports.get_mut(&port_id) {
+                                p.current.remove(&ship_id);
+                            }
+                            dock_waiting_ships(port_id, &mut ports, &mut port_index, &mut ships, &container_store, fuel_density);
+                            Ok(())
+                        }
+                    }
+                    None => Err(format!("Cannot decommission unknown ship {}", ship_id)),
+                }
+            }
+            Operation::AutoLoad { ship_id, strategy } => {
+                match ships.get_mut(&ship_id) {
+                    Some(ship) => {
+                        let loaded = ship.auto_load(strategy, keep_consignments, &mut ports, &mut container_store);
+                        println!("Ship {} auto-loaded containers {:?}", ship_id, loaded);
+                        Ok(())
+                    }
+                    None => Err(format!("Cannot auto-load unknown ship {}", ship_id)),
+                }
+            }
+            Operation::OffloadAll { ship_id } => {
+                match ships.get_mut(&ship_id) {
+                    Some(ship) => {
+                        let offloaded = ship.offload_all(keep_consignments, &mut ports, &mut container_store);
+                        println!("Ship {} offloaded containers {:?}", ship_id, offloaded);
+                        if ship.containers.is_empty() { Ok(()) } else { Err(format!("Ship {} still carries {:?}", ship_id, ship.containers)) }
+                    }
+                    None => Err(format!("Cannot offload unknown ship {}", ship_id)),
+                }
+            }
+            Operation::Reweigh { container_id, weight } => {
+                if weight <= 0 {
+                    Err(format!("Container {} cannot be reweighed to {}: weight must be positive", container_id, weight))
+                } else if let Some(cont) = container_store.get_mut(&container_id) {
+                    // a basic container can turn heavy (or back), so both limits are re-checked
+                    cont.set_weight(weight);
+                    if let Some(Location::Ship(sid)) = locate_container(container_id, &ports, &ships, &container_store) {
+                        let ship = &ships[&sid];
+                        let total = ship.current_total_weight(&container_store);
+                        if total > ship.total_weight_capacity as i64 {
+                            warnings.push(format!("OverweightAfterReweigh: ship {} carries {} over its capacity {} after container {} was reweighed", sid, total, ship.total_weight_capacity, container_id));
+                        }
+                        let heavy = ship.current_heavy_count(&container_store);
+                        if heavy > ship.max_number_of_heavy_containers {
+                            warnings.push(format!("HeavyLimitAfterReweigh: ship {} carries {} heavy containers over its limit {} after container {} was reweighed", sid, heavy, ship.max_number_of_heavy_containers, container_id));
+                        }
+                    }
+                    Ok(())
+                } else {
+                    Err(format!("Cannot reweigh unknown container {}", container_id))
+                }
+            }
+            Operation::FindContainers { kind, min_weight, max_weight } => {
+                match kind.as_deref() {
+                    Some(k) if !ContainerData::KINDS.contains(&k) => Err(format!("Unknown container kind {:?}; expected one of {:?}", k, ContainerData::KINDS)),
+                    _ => {
+                        let found = find_containers(&container_store, |c| {
+                            kind.as_deref().is_none_or(|k| c.kind_name() == k)
+                                && min_weight.is_none_or(|w| c.weight() >= w)
+                                && max_weight.is_none_or(|w| c.weight() <= w)
+                        });
+                        println!("Found containers {:?}", found);
+                        Ok(())
+                    }
+                }
+            }
+            Operation::Undo => {
+                match undo_stack.pop_back() {
+                    Some(snapshot) => {
+                        ports = snapshot.ports;
+                        ships = snapshot.ships;
+                        container_store = snapshot.container_store;
+                        // the restored ports may differ from the indexed ones at the same count
+                        port_index.invalidate();
+                        Ok(())
+                    }
+                    None => Err("Nothing to undo".to_string()),
+                }
+            }
+            Operation::EvacuateToFuel => {
+                let mut ship_ids: Vec<usize> = ships.keys().copied().collect();
+                ship_ids.sort_unstable();
+                for sid in ship_ids {
+                    let ship = ships.get_mut(&sid).unwrap();
+                    let origin = ship.current_port;
+                    if ports.get(&origin).is_some_and(|p| p.has_fuel) {
+                        println!("Ship {} stays at port {} (fuel available)", sid, origin);
+                        continue;
+                    }
+                    // nearest fuelling port first; refuel stops would need fuel themselves, so direct legs are enough
+                    let fuelled: Vec<usize> = port_index.nearest(&ports, origin, usize::MAX).iter()
+                        .map(|&(pid, _)| pid)
+                        .filter(|pid| ports[pid].has_fuel)
+                        .collect();
+                    let reached = fuelled.into_iter().find(|&pid| ship.sail_to(pid, &mut ports, &container_store, fuel_density).is_ok());
+                    match reached {
+                        Some(pid) => {
+                            println!("Ship {} evacuated to port {}", sid, pid);
+                            for p in ports.values_mut() {
+                                p.waiting.retain(|&w| w != sid);
+                            }
+                            dock_waiting_ships(origin, &mut ports, &mut port_index, &mut ships, &container_store, fuel_density);
+                        }
+                        None => println!("Ship {} is stranded at port {}", sid, origin),
+                    }
+                }
+                Ok(())
+            }
+            Operation::SetShipService { ship_id, in_service } => {
+                match ships.get_mut(&ship_id) {
+                    Some(ship) => {
+                        ship.in_service = in_service;
+                        Ok(())
+                    }
+                    None => Err(format!("Cannot change service of unknown ship {}", ship_id)),
+                }
+            }
+            Operation::ShipsCarrying { kind } => {
+                if ContainerData::KINDS.contains(&kind.as_str()) {
+                    for (sid, count) in ships_carrying(&ships, &container_store, &kind) {
+                        println!("Ship {} carries {} {} container(s)", sid, count, kind);
+                    }
+                    Ok(())
+                } else {
+                    Err(format!("Unknown container kind {:?}; expected one of {:?}", kind, ContainerData::KINDS))
+                }
+            }
+            Operation::Bounds => {
+                match ports_bounding_box(&ports) {
+                    Some((lat0, lat1, lon0, lon1)) => println!("Ports span lat {:.2}..{:.2}, lon {:.2}..{:.2}", lat0, lat1, lon0, lon1),
+                    None => println!("No ports to bound"),
+                }
+                Ok(())
+            }
+            Operation::OptimalDepot => {
+                match optimal_depot(&ports) {
+                    Some((pid, total)) => println!("Best depot: {} ({:.2} km to all other ports)", ports[&pid].label(), total),
+                    None => println!("No ports to choose a depot from"),
+                }
+                Ok(())
+            }
+            Operation::EmptyPorts => {
+                let empty = empty_ports(&ports);
+                if empty.is_empty() {
+                    println!("No empty ports");
+                } else {
+                    println!("Empty ports: {}", empty.iter().map(|id| ports[id].label()).collect::<Vec<_>>().join(", "));
+                }
+                Ok(())
+            }
+            Operation::Maintain { ship_id } => {
+                match ships.get_mut(&ship_id) {
+                    Some(ship) => {
+                        log::info!("Ship {} maintained after {:.2} km", ship_id, ship.km_since_maintenance);
+                        ship.km_since_maintenance = 0.0;
+                        Ok(())
+                    }
+                    None => Err(format!("Cannot maintain unknown ship {}", ship_id)),
+                }
+            }
+            Operation::Rebalance { port_id } => {
+                if ports.contains_key(&port_id) {
+                    let moves = rebalance(port_id, keep_consignments, &mut ports, &mut ships, &mut container_store);
+                    println!("Rebalanced ships at port {}: {} container moves", port_id, moves);
+                    Ok(())
+                } else {
+                    Err(format!("Cannot rebalance at unknown port {}", port_id))
+                }
+            }
+            Operation::DistanceMatrix { path } => {
+                let mut ids: Vec<usize> = ports.keys().cloned().collect();
+                ids.sort_unstable();
+                let mut csv = format!("port,{}\n", ids.iter().map(|id| id.to_string()).collect::<Vec<_>>().join(","));
+                for (id, row) in ids.iter().zip(distance_matrix(&ports)) {
+                    csv += &format!("{},{}\n", id, row.iter().map(|d| format!("{:.2}", d)).collect::<Vec<_>>().join(","));
+                }
+                match path {
+                    Some(path) => fs::write(&path, csv).map_err(|e| format!("Cannot write distance matrix to {}: {}", path, e)),
+                    None => {
+                        print!("{}", csv);
+                        Ok(())
+                    }
+                }
+            }
+            Operation::CountContainers => {
+                let (in_ports, on_ships, limbo) = count_containers(&ports, &ships, &container_store);
+                let total = container_store.len();
+                println!("Containers: {} in ports, {} on ships, {} unplaced, {} in store", in_ports, on_ships, limbo, total);
+                if in_ports + on_ships + limbo == total {
+                    Ok(())
+                } else {
+                    Err(format!("Container counts do not reconcile: {} placed + {} unplaced != {} in store", in_ports + on_ships, limbo, total))
+                }
+            }
+            Operation::TransferFuel { from_ship, to_ship, amount } => {
+                match (ships.get(&from_ship), ships.get(&to_ship)) {
+                    _ if amount.is_nan() || amount < 0.0 => Err(format!("Cannot transfer a negative amount of fuel ({})", amount)),
+                    _ if from_ship == to_ship => Err(format!("Ship {} cannot transfer fuel to itself", from_ship)),
+                    (Some(donor), Some(receiver)) => {
+                        if donor.current_port != receiver.current_port {
+                            Err(format!("Ships {} and {} are not at the same port", from_ship, to_ship))
+                        } else {
+                            // limited by what the donor has and what the receiver's tank can take
+                            let moved = amount.min(donor.fuel).min(receiver.max_fuel_capacity - receiver.fuel).max(0.0);
+                            ships.get_mut(&from_ship).unwrap().fuel -= moved;
+                            ships.get_mut(&to_ship).unwrap().fuel += moved;
+                            println!("Ship {} transferred {:.2} fuel to ship {}", from_ship, moved, to_ship);
+                            Ok(())
+                        }
+                    }
+                    (None, _) => Err(format!("Cannot transfer fuel from unknown ship {}", from_ship)),
+                    (_, None) => Err(format!("Cannot transfer fuel to unknown ship {}", to_ship)),
+                }
+            }
+            Operation::Transfer { from_ship, to_ship, container_id } => {
+                let consignment = container_store.get(&container_id).and_then(|c| c.consignment()).filter(|_| keep_consignments);
+                match (ships.get(&from_ship), ships.get(&to_ship)) {
+                    _ if from_ship == to_ship => Err(format!("Ship {} cannot transfer a container to itself", from_ship)),
+                    (Some(donor), Some(receiver)) if donor.current_port != receiver.current_port => {
+                        Err(format!("Ships {} and {} are not at the same port", from_ship, to_ship))
+                    }
+                    _ if consignment.is_some() => {
+                        Err(format!("Container {} belongs to consignment {}; it cannot be transferred on its own", container_id, consignment.unwrap()))
+                    }
+                    (Some(_), Some(_)) => {
+                        // through the port's yard, so the receiver's load runs every can_load check
+                        match ships.get_mut(&from_ship).unwrap().un_load(container_id, &mut ports, &mut container_store) {
+                            Err(e) => Err(format!("Ship {} could not hand over container {}: {:?}", from_ship, container_id, e)),
+                            Ok(()) => match ships.get_mut(&to_ship).unwrap().load(container_id, &mut ports, &mut container_store) {
+                                Ok(()) => Ok(()),
+                                Err(e) => {
+                                    // back onto the donor, where it fitted a moment ago
+                                    ships.get_mut(&from_ship).unwrap().load(container_id, &mut ports, &mut container_store).unwrap();
+                                    Err(format!("Ship {} could not take container {} from ship {}: {:?}", to_ship, container_id, from_ship, e))
+                                }
+                            },
+                        }
+                    }
+                    (None, _) => Err(format!("Cannot transfer from unknown ship {}", from_ship)),
+                    (_, None) => Err(format!("Cannot transfer to unknown ship {}", to_ship)),
+                }
+            }
+            Operation::SetRouteWeather { from_port, to_port, multiplier } => {
+                if !(multiplier.is_finite() && multiplier > 0.0) {
+                    Err(format!("Weather multiplier for {} -> {} must be positive, got {}", from_port, to_port, multiplier))
+                } else if !ports.contains_key(&to_port) {
+                    Err(format!("Cannot set weather towards unknown port {}", to_port))
+                } else {
+                    match ports.get_mut(&from_port) {
+                        Some(p) => {
+                            // calm weather needs no entry
+                            if multiplier == 1.0 {
+                                p.weather.remove(&to_port);
+                            } else {
+                                p.weather.insert(to_port, multiplier);
+                            }
+                            Ok(())
+                        }
+                        None => Err(format!("Cannot set weather from unknown port {}", from_port)),
+                    }
+                }
+            }
+            Operation::RenamePort { port_id, name } => {
+                match port_name_conflict(&name, port_id, &ports) {
+                    Some(conflict) => Err(format!("Cannot rename port {}: {}", port_id, conflict)),
+                    None => match ports.get_mut(&port_id) {
+                        Some(p) => {
+                            p.name = Some(name);
+                            Ok(())
+                        }
+                        None => Err(format!("Cannot rename unknown port {}", port_id)),
+                    },
+                }
+            }
+            Operation::ClearHistory { port_id } => {
+                match ports.get_mut(&port_id) {
+                    Some(p) => {
+                        p.history.clear();
+                        Ok(())
+                    }
+                    None => Err(format!("Cannot clear history of unknown port {}", port_id)),
+                }
+            }
+            Operation::Checkpoint { label } => {
+                checkpoints.insert(label, Snapshot { ports: ports.clone(), ships: ships.clone(), container_store: container_store.clone() });
+                Ok(())
+            }
+            Operation::ClosestReachable { ship_id } => {
+                match ships.get(&ship_id) {
+                    Some(ship) => {
+                        match closest_reachable(ship, &ports, &container_store, fuel_density) {
+                            Some((pid, d)) => println!("Ship {} can reach {} ({:.2} km)", ship_id, ports[&pid].label(), d),
+                            None => println!("Ship {} cannot reach any other port", ship_id),
+                        }
+                        Ok(())
+                    }
+                    None => Err(format!("Cannot find closest port for unknown ship {}", ship_id)),
+                }
+            }
+            Operation::Range { ship_id } => {
+                match ships.get(&ship_id) {
+                    Some(ship) => {
+                        println!("Ship {} range: {:.2} km", ship_id, ship.remaining_range_km(&container_store, fuel_density));
+                        Ok(())
+                    }
+                    None => Err(format!("Cannot report range of unknown ship {}", ship_id)),
+                }
+            }
+        };
+        let count = run_summary.operations.entry(kind).or_default();
+        count.total += 1;
+        if outcome.is_ok() {
+            count.succeeded += 1;
+        }
+        if let Err(e) = outcome {
+            if fail_fast {
+                return Err(format!("operation {} ({}) failed: {}", index, kind, e));
+            }
+            log::warn!("{}", e);
+        }
+        if timing {
+            let entry = timings.entry(kind).or_insert((Duration::ZERO, 0));
+            entry.0 += started.elapsed();
+            entry.1 += 1;
+        }
+    }
+
+    Ok(RunState { ports, ships, container_store, warnings, port_index, timings, summary: run_summary, checkpoints })
+}
+
+// the output JSON: one entry per port with its yard and docked ships,
+// plus the optional histogram and any warnings, which are logged here as well
+fn build_output(state: &mut RunState, input: &InputFile) -> serde_json::Value {
+    let (ports, ships, container_store) = (&state.ports, &state.ships, &state.container_store);
+    let warnings = &mut state.warnings;
+    let mut out_map: serde_json::Map<String, serde_json::Value> = serde_json::Map::new();
+    let mut port_ids: Vec<_> = ports.keys().cloned().collect();
+    port_ids.sort_unstable();
+    for pid in port_ids {
+        let p = ports.get(&pid).unwrap();
+        // containers by kind
+        let mut basic = Vec::new();
+        let mut heavy = Vec::new();
+        let mut refrigerated = Vec::new();
+        let mut liquid = Vec::new();
+        for &cid in p.containers.iter() {
+            let cont = match container_store.get(&cid) {
+                Some(c) => c,
+                None => {
+                    warnings.push(format!("container {} in {} is missing from the container store", cid, p.label()));
+                    continue;
+                }
+            };
+            match cont {
+                ContainerData::Basic { .. } => basic.push(cid),
+                ContainerData::Heavy { .. } => heavy.push(cid),
+                ContainerData::Refrigerated { .. } => refrigerated.push(cid),
+                ContainerData::Liquid { .. } => liquid.push(cid),
+            }
+        }
+        for list in [&mut basic, &mut heavy, &mut refrigerated, &mut liquid] {
+            sort_output_ids(list, input.output_sort, container_store);
+        }
+        let mut ship_map = serde_json::Map::new();
+        let mut ship_ids: Vec<_> = p.current.iter().cloned().collect();
+        ship_ids.sort_unstable();
+        for sid in ship_ids {
+            let s = ships.get(&sid).unwrap();
+            let mut sbasic = Vec::new();
+            let mut sheavy = Vec::new();
+            let mut sref = Vec::new();
+            let mut sliq = Vec::new();
+            for &cid in s.get_current_containers_sorted().iter() {
+                let cont = match container_store.get(&cid) {
+                    Some(c) => c,
+                    None => {
+                        warnings.push(format!("container {} on ship {} is missing from the container store", cid, sid));
+                        continue;
+                    }
+                };
+                match cont {
+                    ContainerData::Basic { .. } => sbasic.push(cid),
+                    ContainerData::Heavy { .. } => sheavy.push(cid),
+                    ContainerData::Refrigerated { .. } => sref.push(cid),
+                    ContainerData::Liquid { .. } => sliq.push(cid),
+                }
+            }
+            for list in [&mut sbasic, &mut sheavy, &mut sref, &mut sliq] {
+                sort_output_ids(list, input.output_sort, container_store);
+            }
+            let sjson = serde_json::json!({
+                "fuel_left": output_number(s.fuel),
+                "fuel_unit": input.fuel_unit,
+                "fuel_percent": output_number(s.fuel_percent()),
+                "cargo_value": output_number(s.cargo_value(container_store)),
+                "km_per_fuel": s.km_per_fuel().map(output_number),
+                "basic_container": sbasic,
+                "heavy_container": sheavy,
+                "refrigerated_container": sref,
+                "liquid_container": sliq
+            });
+            ship_map.insert(format!("ship_{}", sid), sjson);
+        }
+
+        let port_json = serde_json::json!({
+            "lat": output_number(p.latitude),
+            "lon": output_number(p.longitude),
+            "basic_container": basic,
+            "heavy_container": heavy,
+            "refrigerated_container": refrigerated,
+            "liquid_container": liquid,
+            "yard_weight": p.total_container_weight(container_store),
+            "ships": ship_map
+        });
+        out_map.insert(p.label(), port_json);
+    }
+
+    if let Some(buckets) = &input.weight_histogram {
+        out_map.insert("weight_histogram".to_string(), serde_json::json!(weight_histogram(container_store, buckets)));
+    }
+
+    if !warnings.is_empty() {
+        for w in warnings.iter() {
+            log::warn!("{}", w);
+        }
+        out_map.insert("warnings".to_string(), serde_json::json!(warnings));
+    }
+
+    serde_json::Value::Object(out_map)
+}
+
+// small deterministic PRNG (SplitMix64) so generated scenarios only depend on the seed
+struct SplitMix64(u64);
+
+impl SplitMix64 {
+    fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+    // uniform in 0..n (n > 0)
+    fn below(&mut self, n: usize) -> usize {
+        (self.next_u64() % n as u64) as usize
+    }
+    // uniform in lo..hi, rounded to 2 decimals
+    fn range(&mut self, lo: f64, hi: f64) -> f64 {
+        let unit = (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64;
+        ((lo + unit * (hi - lo)) * 100.0).round() / 100.0
+    }
+}
+
+// creates every port, ship and container first, then `n_ops` loads, unloads, sails and refuels.
+// the generator follows where ships and containers should be so that loads pick containers at
+// the ship's port and unloads pick containers aboard; capacity and fuel are not simulated, so
+// some of them may still be rejected at run time.
+fn generate_scenario(seed: u64, n_ports: usize, n_ships: usize, n_containers: usize, n_ops: usize) -> InputFile {
+    let mut rng = SplitMix64(seed);
+    let mut operations = Vec::new();
+    let n_ports = n_ports.max(1);
+    for id in 1..=n_ports {
+        operations.push(Operation::CreatePort { id, latitude: rng.range(-60.0, 60.0), longitude: rng.range(-180.0, 180.0), max_berths: None, name: None, has_fuel: None });
+    }
+    let mut ship_port = Vec::new();
+    for id in 1..=n_ships {
+        let port_id = 1 + rng.below(n_ports);
+        ship_port.push(port_id);
+        operations.push(Operation::CreateShip {
+            id,
+            port_id,
+            total_weight_capacity: 10000 + 1000 * rng.below(40) as i32,
+            limits: ShipLimitsSpec::Flat {
+                max_number_of_all_containers: 5 + rng.below(10),
+                max_number_of_heavy_containers: 2 + rng.below(5),
+                max_number_of_refrigerated_containers: 1 + rng.below(3),
+                max_number_of_liquid_containers: 1 + rng.below(3),
+            },
+            fuel_consumption_per_km: rng.range(0.5, 3.0),
+            max_fuel_capacity: None,
+            stacking: None,
+            max_liquid_weight: None,
+            max_refrigerated_weight: None,
+            allowed_kinds: None,
+            wear_per_1000km: None,
+            upsert: false,
+        });
+    }
+    // where each container is: Ok(port_id) ashore, Err(ship index) aboard
+    let mut container_at: Vec<Result<usize, usize>> = Vec::new();
+    for id in 1..=n_containers {
+        let port_id = 1 + rng.below(n_ports);
+        container_at.push(Ok(port_id));
+        let special = match rng.below(5) {
+            0 => Some("R".to_string()),
+            1 => Some("L".to_string()),
+            _ => None,
+        };
+        operations.push(Operation::CreateContainer { id, weight: 500 + 100 * rng.below(56) as i32, special, port_id, consumption_override: None, value: None, flat_consumption: None, consignment: None });
+    }
+    if n_ships == 0 {
+        return InputFile { operations, ..Default::default() };
+    }
+    for _ in 0..n_ops {
+        let s = rng.below(n_ships);
+        let here: Vec<usize> = (0..container_at.len()).filter(|&c| container_at[c] == Ok(ship_port[s])).collect();
+        let aboard: Vec<usize> = (0..container_at.len()).filter(|&c| container_at[c] == Err(s)).collect();
+        match rng.below(4) {
+            0 if !here.is_empty() => {
+                let c = here[rng.below(here.len())];
+                container_at[c] = Err(s);
+                operations.push(Operation::Load { ship_id: s + 1, container_id: c + 1 });
+            }
+            1 if !aboard.is_empty() => {
+                let c = aboard[rng.below(aboard.len())];
+                container_at[c] = Ok(ship_port[s]);
+                operations.push(Operation::Unload { ship_id: s + 1, container_id: c + 1 });
+            }
+            2 if n_ports > 1 => {
+                let dest = (ship_port[s] + rng.below(n_ports - 1)) % n_ports + 1;
+                ship_port[s] = dest;
+                operations.push(Operation::Sail { ship_id: s + 1, dest_port_id: dest });
+            }
+            _ => operations.push(Operation::Refuel { ship_id: s + 1, amount: rng.range(1000.0, 50000.0) }),
+        }
+    }
+    InputFile { operations, ..Default::default() }
+}
+
+// one LineString feature per ship that has sailed, through the coordinates of the ports it visited
+// ([lon, lat] as GeoJSON orders them); a ship still at its starting port has no route to draw
+fn routes_geojson(ships: &HashMap<usize, Ship>, ports: &HashMap<usize, Port>) -> serde_json::Value {
+    let mut ship_ids: Vec<_> = ships.keys().cloned().collect();
+    ship_ids.sort_unstable();
+    let features: Vec<serde_json::Value> = ship_ids.iter().map(|sid| &ships[sid]).filter(|s| s.ports_visited.len() > 1).map(|s| {
+        let coordinates: Vec<[f64; 2]> = s.ports_visited.iter().filter_map(|pid| ports.get(pid)).map(|p| [p.longitude, p.latitude]).collect();
+        serde_json::json!({
+            "type": "Feature",
+            "geometry": { "type": "LineString", "coordinates": coordinates },
+            "properties": { "ship_id": s.id, "km_sailed": output_number(s.km_sailed) }
+        })
+    }).collect();
+    serde_json::json!({ "type": "FeatureCollection", "features": features })
+}
+
+// fuel a ship's planned sails need against what it is given; see `project_fuel_demand`
+#[derive(Debug, Default, Serialize)]
+pub struct FuelProjection {
+    pub demand: f64,    // summed cost of every planned leg
+    pub refuelled: f64, // fuel actually taken on by Refuel (tank limits applied)
+    pub shortfall: f64, // fuel missing over the legs that could not be covered
+}
+
+impl FuelProjection {
+    pub fn sufficient(&self) -> bool {
+        self.shortfall <= 0.0
+    }
+}
+
+// dry run of the fuel side of a scenario: replays port, ship and container creation, weather,
+// loads, unloads, refuels and sails on a private copy of the world. every Sail is taken as a
+// direct leg that happens even when it is short of fuel (the tank just runs dry), berths are
+// not modelled and other operations are skipped, so with enough fuel the demand equals what a
+// real run burns
+fn project_fuel_demand(input: &InputFile) -> HashMap<usize, FuelProjection> {
+    let mut ports: HashMap<usize, Port> = HashMap::new();
+    let mut ships: HashMap<usize, Ship> = HashMap::new();
+    let mut container_store: HashMap<usize, ContainerData> = HashMap::new();
+    let mut projection: HashMap<usize, FuelProjection> = HashMap::new();
+    for op in input.operations.iter() {
+        match op {
+            Operation::CreatePort { id, latitude, longitude, .. } => {
+                ports.insert(*id, Port::new(*id, *latitude, *longitude));
+            }
+            Operation::CreatePorts { ports: specs } => {
+                for spec in specs {
+                    ports.insert(spec.id, Port::new(spec.id, spec.latitude, spec.longitude));
+                }
+            }
+            Operation::CreateShip { id, port_id, total_weight_capacity, limits, fuel_consumption_per_km, max_fuel_capacity, stacking, max_liquid_weight, max_refrigerated_weight, allowed_kinds, wear_per_1000km, .. } => {
+                if !ports.contains_key(port_id) {
+                    continue;
+                }
+                let ShipLimits { all: max_number_of_all_containers, heavy: max_number_of_heavy_containers, refrigerated: max_number_of_refrigerated_containers, liquid: max_number_of_liquid_containers } = limits.resolve();
+                let mut s = Ship::new(*id, *port_id, *total_weight_capacity, max_number_of_all_containers, max_number_of_heavy_containers, max_number_of_refrigerated_containers, max_number_of_liquid_containers, *fuel_consumption_per_km);
+                s.max_fuel_capacity = max_fuel_capacity.unwrap_or(s.max_fuel_capacity);
+                s.stacking = stacking.unwrap_or_default();
+                s.max_liquid_weight = max_liquid_weight.unwrap_or(s.max_liquid_weight);
+                s.max_refrigerated_weight = max_refrigerated_weight.unwrap_or(s.max_refrigerated_weight);
+                s.allowed_kinds = allowed_kinds.clone();
+                s.wear_per_1000km = wear_per_1000km.unwrap_or(0.0);
+                ships.insert(*id, s);
+                projection.entry(*id).or_default();
+            }
+            Operation::CreateContainer { id, weight, special, port_id, consumption_override, value, flat_consumption, consignment } => {
+                container_store.insert(*id, build_container(*id, *weight, special.as_deref(), ContainerExtras { consumption_override: *consumption_override, value: *value, flat_consumption: flat_consumption.unwrap_or(0.0), consignment: *consignment }));
+                place_container(*id, *port_id, &mut ports, &mut PortIndex::default());
+            }
+            Operation::SetRouteWeather { from_port, to_port, multiplier } => {
+                if let Some(p) = ports.get_mut(from_port) {
+                    p.weather.insert(*to_port, *multiplier);
+                }
+            }
+            Operation::Load { ship_id, container_id } => {
+                if let Some(ship) = ships.get_mut(ship_id) {
+                    let _ = ship.load(*container_id, &mut ports, &mut container_store);
+                }
+            }
+            Operation::Unload { ship_id, container_id } => {
+                if let Some(ship) = ships.get_mut(ship_id) {
+                    let _ = ship.un_load(*container_id, &mut ports, &mut container_store);
+                }
+            }
+            Operation::Refuel { ship_id, amount } => {
+                if let Some(ship) = ships.get_mut(ship_id) {
+                    let before = ship.fuel;
+                    ship.re_fuel(*amount);
+                    projection.entry(*ship_id).or_default().refuelled += ship.fuel - before;
+                }
+            }
+            Operation::Sail { ship_id, dest_port_id } => {
+                let ship = match ships.get_mut(ship_id) {
+                    Some(s) if s.current_port != *dest_port_id => s,
+                    _ => continue,
+                };
+                let cost = match (ports.get(&ship.current_port), ports.get(dest_port_id)) {
+                    (Some(from), Some(dest)) => ship.voyage_fuel_cost(from, dest, &container_store, input.fuel_density),
+                    _ => continue,
+                };
+                let entry = projection.entry(*ship_id).or_default();
+                entry.demand += cost;
+                entry.shortfall += (cost - ship.fuel).max(0.0);
+                ship.fuel = (ship.fuel - cost).max(0.0);
+                ship.km_since_maintenance += ports[&ship.current_port].get_distance(&ports[dest_port_id]);
+                ship.current_port = *dest_port_id;
+            }
+            Operation::Maintain { ship_id } => {
+                if let Some(ship) = ships.get_mut(ship_id) {
+                    ship.km_since_maintenance = 0.0;
+                }
+            }
+            _ => {}
+        }
+    }
+    projection
+}
+
+#[derive(Debug, Default, Serialize)]
+struct OpCount {
+    total: usize,
+    succeeded: usize,
+}
+
+// aggregate figures about a run, printed with --summary
+#[derive(Debug, Default, Serialize)]
+struct Summary {
+    cargo_centroid: Option<(f64, f64)>,
+    fleet_utilization: FleetUtil,
+    stranded_ships: Vec<usize>,
+    net_flow: BTreeMap<usize, i64>, // per port id
+    container_fuel: BTreeMap<usize, f64>, // fuel charged to each container by Sail, per container id
+    operations: BTreeMap<&'static str, OpCount>, // per operation kind
+}
+
+// sizes used by --gen
+const GEN_PORTS: usize = 8;
+const GEN_SHIPS: usize = 4;
+const GEN_CONTAINERS: usize = 30;
+const GEN_OPS: usize = 100;
+
+fn main() {
+    // diagnostics go through the logger (RUST_LOG controls verbosity); results stay on stdout
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info")).init();
+
+    // usage: lab2 [--json5] [--timing] [--summary] [--fail-fast] [--project-fuel] [--geojson routes.geojson] [--diff label1 label2] [input.json ...] [output.json]
+    //        lab2 --gen <seed> [scenario.json]
+    //        inputs ending in .json5 / .toml are read as JSON5 / TOML
+    let mut json5 = false;
+    let mut timing = false;
+    // print aggregate figures about the final world
+    let mut summary = false;
+    // print the planned fuel demand per ship before running
+    let mut project_fuel = false;
+    // abort on the first skipped operation instead of warning and carrying on
+    let mut fail_fast = false;
+    // write a generated scenario instead of running one
+    let mut gen_seed: Option<u64> = None;
+    // also write the ships' routes as GeoJSON
+    let mut geojson_path: Option<String> = None;
+    // compare two Checkpoint labels after the run
+    let mut diff_labels: Option<(String, String)> = None;
+    let mut paths = Vec::new();
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--gen" => match args.next().and_then(|v| v.parse().ok()) {
+                Some(seed) => gen_seed = Some(seed),
+                None => {
+                    eprintln!("--gen needs a numeric seed");
+                    std::process::exit(1);
+                }
+            },
+            "--geojson" => match args.next() {
+                Some(path) => geojson_path = Some(path),
+                None => {
+                    eprintln!("--geojson needs an output path");
+                    std::process::exit(1);
+                }
+            },
+            "--diff" => match (args.next(), args.next()) {
+                (Some(a), Some(b)) => diff_labels = Some((a, b)),
+                _ => {
+                    eprintln!("--diff needs two checkpoint labels");
+                    std::process::exit(1);
+                }
+            },
+            "--json5" => json5 = true,
+            "--timing" => timing = true,
+            "--summary" => summary = true,
+            "--project-fuel" => project_fuel = true,
+            "--fail-fast" => fail_fast = true,
+            _ => paths.push(arg),
+        }
+    }
+    if let Some(seed) = gen_seed {
+        let scenario_path = paths.first().cloned().unwrap_or_else(|| "scenario.json".to_string());
+        let scenario = generate_scenario(seed, GEN_PORTS, GEN_SHIPS, GEN_CONTAINERS, GEN_OPS);
+        let text = serde_json::to_string_pretty(&scenario).unwrap();
+        fs::write(&scenario_path, text).unwrap_or_else(|_| panic!("unable to write {}", scenario_path));
+        println!("Wrote scenario for seed {} to {}", seed, scenario_path);
+        return;
+    }
+    // for demo: default to "input.json" / "output.json" in the current directory.
+    // with several paths the last one is the output and the rest are inputs run in order
+    let (input_paths, output_path) = match paths.len() {
+        0 => (vec!["input.json".to_string()], "output.json".to_string()),
+        1 => (paths, "output.json".to_string()),
+        _ => {
+            let output_path = paths.pop().unwrap();
+            (paths, output_path)
+        }
+    };
+
+    let mut input = InputFile::default();
+    for input_path in input_paths.iter() {
+        let input_text = fs::read_to_string(input_path).unwrap_or_else(|_| panic!("{} not found", input_path));
+        match parse_input(&input_text, InputFormat::for_path(input_path, json5)) {
+            Ok(part) => input.merge(part),
+            Err(e) => {
+                eprintln!("{}: {}", input_path, e);
+                std::process::exit(1);
+            }
+        }
+    }
+
+    let fuel_density = input.fuel_density;
+    if !(fuel_density.is_finite() && fuel_density > 0.0) {
+        eprintln!("fuel_density must be positive, got {}", fuel_density);
+        std::process::exit(1);
+    }
+    if project_fuel {
+        let projection: BTreeMap<usize, FuelProjection> = project_fuel_demand(&input).into_iter().collect();
+        for (sid, p) in projection.iter() {
+            let verdict = if p.sufficient() { "enough" } else { "short" };
+            println!("Ship {} needs {:.2} fuel for its sails, takes on {:.2}: {} (short by {:.2})", sid, p.demand, p.refuelled, verdict, p.shortfall);
+        }
+    }
+
+    let mut state = match run(&mut input, fail_fast, timing) {
+        Ok(state) => state,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    };
+
+    if timing {
+        println!("Operation timings:");
+        for (kind, (total, count)) in state.timings.iter() {
+            println!("  {:<18} {:>6} ops {:>12.3?}", kind, count, total);
+        }
+        println!("  port index rebuilt {} times", state.port_index.rebuilds);
+    }
+
+    if summary {
+        state.summary.cargo_centroid = cargo_centroid(&state.ports, &state.container_store);
+        state.summary.fleet_utilization = fleet_utilization(&state.ships, &state.container_store);
+        state.summary.stranded_ships = stranded_ships(&state.ships, &state.ports, &state.container_store, fuel_density);
+        state.summary.net_flow = state.ports.values().map(|p| (p.id, p.net_flow())).collect();
+        println!("Summary: {}", serde_json::to_string_pretty(&state.summary).unwrap());
+    }
+
+    if let Some((from, to)) = diff_labels {
+        match (state.checkpoints.get(&from), state.checkpoints.get(&to)) {
+            (Some(a), Some(b)) => {
+                let diff = diff_snapshots(a, b);
+                println!("Changes from checkpoint {:?} to {:?}:", from, to);
+                if diff.is_empty() {
+                    println!("  none");
+                }
+                for sid in diff.ships_added.iter() {
+                    println!("  ship {} added", sid);
+                }
+                for sid in diff.ships_removed.iter() {
+                    println!("  ship {} removed", sid);
+                }
+                for (sid, was, now) in diff.ships_moved.iter() {
+                    println!("  ship {} moved from port {} to port {}", sid, was, now);
+                }
+                for (sid, delta) in diff.fuel_deltas.iter() {
+                    println!("  ship {} fuel {:+.2}", sid, delta);
+                }
+                for (cid, was, now) in diff.containers_relocated.iter() {
+                    println!("  container {} {:?} -> {:?}", cid, was, now);
+                }
+            }
+            (a, _) => {
+                let missing = if a.is_none() { &from } else { &to };
+                state.warnings.push(format!("--diff: no checkpoint labelled {:?}", missing));
+            }
+        }
+    }
+
+    let out_value = build_output(&mut state, &input);
+    let out_text = serde_json::to_string_pretty(&out_value).unwrap();
+    fs::write(&output_path, out_text).unwrap_or_else(|_| panic!("unable to write {}", output_path));
+    println!("Finished. Wrote {}", output_path);
+
+    if let Some(path) = geojson_path {
+        let text = serde_json::to_string_pretty(&routes_geojson(&state.ships, &state.ports)).unwrap();
+        fs::write(&path, text).unwrap_or_else(|_| panic!("unable to write {}", path));
+        println!("Wrote routes to {}", path);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // runs a scenario and returns the final state with its output
+    fn run_as(text: &str, format: InputFormat) -> (RunState, serde_json::Value) {
+        let mut input = parse_input(text, format).unwrap();
+        let mut state = run(&mut input, false, false).unwrap();
+        let output = build_output(&mut state, &input);
+        (state, output)
+    }
+
+    fn run_json(text: &str) -> (RunState, serde_json::Value) {
+        run_as(text, InputFormat::Json)
+    }
+
+    // two ports a degree apart, a ship at port 0 and one basic container loaded and sailed to port 1
+    const VOYAGE: &str = r#"{"operations": [
+        {"createport": {"id": 0, "latitude": 0.0, "longitude": 0.0}},
+        {"createport": {"id": 1, "latitude": 0.0, "longitude": 1.0}},
+        {"createship": {"id": 0, "port_id": 0, "total_weight_capacity": 100, "max_number_of_all_containers": 5, "max_number_of_heavy_containers": 1, "max_number_of_refrigerated_containers": 1, "max_number_of_liquid_containers": 1, "fuel_consumption_per_km": 1.0}},
+        {"createcontainer": {"id": 7, "weight": 10, "port_id": 0}},
+        {"refuel": {"ship_id": 0, "amount": 1000.0}},
+        {"load": {"ship_id": 0, "container_id": 7}},
+        {"sail": {"ship_id": 0, "dest_port_id": 1}}
+    ]}"#;
+
+    // the createship op VOYAGE uses, for ship `id` docked at `port_id`
+    fn createship_json(id: usize, port_id: usize) -> String {
+        format!(r#"{{"createship": {{"id": {id}, "port_id": {port_id}, "total_weight_capacity": 100, "max_number_of_all_containers": 5, "max_number_of_heavy_containers": 1, "max_number_of_refrigerated_containers": 1, "max_number_of_liquid_containers": 1, "fuel_consumption_per_km": 1.0}}}}"#)
+    }
+
+    // ports numbered from 0 at the given (lat, lon)
+    fn ports_at(coords: &[(f64, f64)]) -> HashMap<usize, Port> {
+        coords.iter().enumerate().map(|(id, &(lat, lon))| (id, Port::new(id, lat, lon))).collect()
+    }
+
+    // a ship with roomy limits docked at `port_id`
+    fn ship_at(id: usize, port_id: usize, ports: &mut HashMap<usize, Port>) -> Ship {
+        ports.get_mut(&port_id).unwrap().incoming_ship(id);
+        Ship::new(id, port_id, 100_000, 40, 40, 40, 40, 1.0)
+    }
+
+    // creates a container in the store and puts it in a port's yard
+    fn stock(id: usize, weight: i32, special: Option<&str>, port_id: usize, ports: &mut HashMap<usize, Port>, store: &mut HashMap<usize, ContainerData>) {
+        let extras = ContainerExtras::default();
+        let cont = match special {
+            Some("R") => ContainerData::Refrigerated { id, weight, extras },
+            Some("L") => ContainerData::Liquid { id, weight, extras },
+            _ if weight <= 3000 => ContainerData::Basic { id, weight, extras },
+            _ => ContainerData::Heavy { id, weight, extras },
+        };
+        store.insert(id, cont);
+        ports.get_mut(&port_id).unwrap().containers.insert(id);
+    }
+
+    #[test]
+    fn range_pays_for_cargo_before_distance() {
+        let mut ports = ports_at(&[(0.0, 0.0)]);
+        let mut store = HashMap::new();
+        let mut ship = ship_at(0, 0, &mut ports);
+        ship.fuel_consumption_per_km = 2.0;
+        ship.fuel = 1000.0;
+        assert_eq!(ship.remaining_range_km(&store, 1.0), 500.0);
+        // a basic container burns 2.5 per unit of weight: 250 per voyage
+        stock(1, 100, None, 0, &mut ports, &mut store);
+        ship.load(1, &mut ports, &mut store).unwrap();
+        assert_eq!(ship.remaining_range_km(&store, 1.0), 375.0);
+        // cargo alone needing more than the tank clamps at zero
+        stock(2, 500, None, 0, &mut ports, &mut store);
+        ship.load(2, &mut ports, &mut store).unwrap();
+        assert_eq!(ship.remaining_range_km(&store, 1.0), 0.0);
+    }
+
+    #[test]
+    fn full_port_turns_ships_away_until_a_berth_frees() {
+        let mut ports = ports_at(&[(0.0, 0.0), (0.0, 0.1)]);
+        ports.get_mut(&1).unwrap().max_berths = 2;
+        let store = HashMap::new();
+        let mut fleet: Vec<Ship> = (0..3).map(|id| ship_at(id, 0, &mut ports)).collect();
+        for ship in fleet.iter_mut() {
+            ship.fuel = 1000.0;
+        }
+        fleet[0].sail_to(1, &mut ports, &store, 1.0).unwrap();
+        fleet[1].sail_to(1, &mut ports, &store, 1.0).unwrap();
+        assert_eq!(fleet[2].sail_to(1, &mut ports, &store, 1.0), Err(ShipOpError::NoBerthAvailable));
+        assert_eq!(fleet[2].current_port, 0);
+        assert!(ports[&0].current.contains(&2));
+        fleet[0].sail_to(0, &mut ports, &store, 1.0).unwrap();
+        fleet[2].sail_to(1, &mut ports, &store, 1.0).unwrap();
+        assert_eq!(ports[&1].current, HashSet::from([1, 2]));
+    }
+
+    #[test]
+    fn parse_error_names_the_operation_and_the_missing_field() {
+        let text = r#"{"operations": [
+            {"createport": {"id": 0, "latitude": 0.0, "longitude": 0.0}},
+            {"createport": {"id": 1, "latitude": 0.0, "longitude": 1.0}},
+            {"refuel": {"ship_id": 0, "amount": 10.0}},
+            {"createport": {"id": 2, "latitude": 1.0, "longitde": 1.0}}
+        ]}"#;
+        assert_eq!(parse_input(text, InputFormat::Json).err().as_deref(), Some("invalid JSON: CreatePort at index 3 missing 'longitude'"));
+        // the same slip in a JSON5 file is explained the same way
+        assert_eq!(parse_input(text, InputFormat::Json5).err().as_deref(), Some("invalid JSON5: CreatePort at index 3 missing 'longitude'"));
+    }
+
+    #[test]
+    fn histogram_bounds_are_lower_inclusive() {
+        let mut ports = ports_at(&[(0.0, 0.0)]);
+        let mut store = HashMap::new();
+        for (id, weight) in [999, 1000, 2999, 3000, 5000].into_iter().enumerate() {
+            stock(id, weight, None, 0, &mut ports, &mut store);
+        }
+        let expected = vec![("0-1000".to_string(), 1), ("1000-3000".to_string(), 2), ("3000+".to_string(), 2)];
+        assert_eq!(weight_histogram(&store, &[1000, 3000]), expected);
+        // with no bounds everything lands in the trailing bucket
+        assert_eq!(weight_histogram(&store, &[]), vec![("0+".to_string(), 5)]);
+    }
+
+    #[test]
+    fn json5_scenario_matches_strict_json() {
+        let json5 = r#"{
+            // same voyage as VOYAGE, written the lenient way
+            operations: [
+                {createport: {id: 0, latitude: 0.0, longitude: 0.0}},
+                {createport: {id: 1, latitude: 0.0, longitude: 1.0},},
+                {createship: {id: 0, port_id: 0, total_weight_capacity: 100, max_number_of_all_containers: 5, max_number_of_heavy_containers: 1, max_number_of_refrigerated_containers: 1, max_number_of_liquid_containers: 1, fuel_consumption_per_km: 1.0}},
+                /* cargo */
+                {createcontainer: {id: 7, weight: 10, port_id: 0}},
+                {refuel: {ship_id: 0, amount: 1000.0}},
+                {load: {ship_id: 0, container_id: 7}},
+                {sail: {ship_id: 0, dest_port_id: 1}},
+            ],
+        }"#;
+        assert!(parse_input(json5, InputFormat::Json).is_err());
+        assert_eq!(run_as(json5, InputFormat::Json5).1, run_json(VOYAGE).1);
+    }
+
+    #[test]
+    fn cargo_is_dropped_nearest_port_first() {
+        let mut ports = ports_at(&[(0.0, 0.0), (0.0, 1.0), (0.0, 2.0)]);
+        let mut store = HashMap::new();
+        let mut ship = ship_at(0, 0, &mut ports);
+        ship.fuel = 10_000.0;
+        for id in 1..=3 {
+            stock(id, 10, None, 0, &mut ports, &mut store);
+            ship.load(id, &mut ports, &mut store).unwrap();
+        }
+        // container 9 is not aboard, so its delivery fails without holding up the rest
+        let assignments = [(1, 2), (2, 1), (3, 1), (9, 1)];
+        let report = distribute_cargo(&mut ship, &assignments, false, &mut ports, &mut PortIndex::default(), &mut store, 1.0);
+        assert_eq!(report, vec![(1, 2, true), (2, 1, true), (3, 1, true), (9, 1, false)]);
+        // port 1 is nearer, so it is visited on the way to port 2
+        assert!(ports[&1].history.contains(&0));
+        assert_eq!(ship.current_port, 2);
+        assert_eq!(ports[&1].containers, HashSet::from([2, 3]));
+        assert_eq!(ports[&2].containers, HashSet::from([1]));
+        assert!(ship.containers.is_empty());
+    }
+
+    #[test]
+    fn locate_container_follows_a_load() {
+        let mut ports = ports_at(&[(0.0, 0.0)]);
+        let mut store = HashMap::new();
+        stock(4, 100, None, 0, &mut ports, &mut store);
+        let mut ship = ship_at(9, 0, &mut ports);
+        assert_eq!(locate_container(4, &ports, &HashMap::new(), &store), Some(Location::Port(0)));
+        ship.load(4, &mut ports, &mut store).unwrap();
+        let ships = HashMap::from([(9, ship)]);
+        assert_eq!(locate_container(4, &ports, &ships, &store), Some(Location::Ship(9)));
+        assert_eq!(locate_container(5, &ports, &ships, &store), None);
+    }
+
+    #[test]
+    fn equidistant_refuel_stops_break_ties_by_lower_id() {
+        // stops 1 and 2 mirror each other across the equator, so both legs tie exactly
+        for _ in 0..10 {
+            let mut ports = ports_at(&[(0.0, 0.0), (-0.5, 1.0), (0.5, 1.0), (0.0, 2.0)]);
+            let mut ship = ship_at(0, 0, &mut ports);
+            ship.fuel = 150.0;
+            sail_with_refuel(&mut ship, 3, &mut ports, &mut PortIndex::default(), &HashMap::new(), 1.0).unwrap();
+            assert_eq!(ship.current_port, 3);
+            assert!(ports[&1].history.contains(&0));
+            assert!(!ports[&2].history.contains(&0));
+        }
+    }
+
+    #[test]
+    fn refuel_falls_through_to_a_farther_stop_that_works() {
+        // stop 1 is nearest but leaves the destination out of reach even with a full refuel; stop 2 is on the way
+        let mut ports = ports_at(&[(0.0, 0.0), (0.0, -0.5), (0.0, 1.0), (0.0, 2.0)]);
+        let mut ship = ship_at(0, 0, &mut ports);
+        ship.fuel_consumption_per_km = 100.0;
+        ship.fuel = 15_000.0;
+        assert!(ports[&0].get_distance(&ports[&3]) * ship.fuel_consumption_per_km > ship.fuel);
+        sail_with_refuel(&mut ship, 3, &mut ports, &mut PortIndex::default(), &HashMap::new(), 1.0).unwrap();
+        assert_eq!(ship.current_port, 3);
+        assert!(ports[&2].history.contains(&0));
+        assert!(!ports[&1].history.contains(&0));
+    }
+
+    #[test]
+    fn lifo_ship_only_unloads_the_top_container() {
+        let mut ports = ports_at(&[(0.0, 0.0)]);
+        let mut store = HashMap::new();
+        stock(1, 100, None, 0, &mut ports, &mut store);
+        stock(2, 100, None, 0, &mut ports, &mut store);
+        let mut ship = ship_at(0, 0, &mut ports);
+        ship.stacking = StackMode::Lifo;
+        ship.load(1, &mut ports, &mut store).unwrap();
+        ship.load(2, &mut ports, &mut store).unwrap();
+        assert_eq!(ship.un_load(1, &mut ports, &mut store), Err(ShipOpError::BuriedContainer));
+        assert_eq!(ship.containers, vec![1, 2]);
+        ship.un_load(2, &mut ports, &mut store).unwrap();
+        ship.un_load(1, &mut ports, &mut store).unwrap();
+        assert!(ship.containers.is_empty());
+    }
+
+    #[test]
+    fn voyage_fuel_cost_is_distance_times_rate_plus_cargo() {
+        let mut ports = ports_at(&[(0.0, 0.0), (0.0, 1.0)]);
+        let mut store = HashMap::new();
+        let mut ship = ship_at(0, 0, &mut ports);
+        ship.fuel_consumption_per_km = 2.0;
+        // basic 100 burns 250, heavy 4000 burns 12000
+        for (id, weight) in [(1, 100), (2, 4000)] {
+            stock(id, weight, None, 0, &mut ports, &mut store);
+            ship.load(id, &mut ports, &mut store).unwrap();
+        }
+        let degree_km = 6371.0 * PI / 180.0;
+        let cost = ship.voyage_fuel_cost(&ports[&0], &ports[&1], &store, 1.0);
+        assert!((cost - (2.0 * degree_km + 12_250.0)).abs() < 1e-9, "cost {}", cost);
+        ship.fuel = 20_000.0;
+        ship.sail_to(1, &mut ports, &store, 1.0).unwrap();
+        assert!((ship.fuel - (20_000.0 - cost)).abs() < 1e-9);
+    }
+
+    #[test]
+    fn consumption_override_changes_only_its_container() {
+        let mut ports = ports_at(&[(0.0, 0.0)]);
+        let mut store = HashMap::new();
+        let mut ship = ship_at(0, 0, &mut ports);
+        for id in [1, 2] {
+            stock(id, 100, Some("R"), 0, &mut ports, &mut store);
+            ship.load(id, &mut ports, &mut store).unwrap();
+        }
+        assert_eq!(ship.container_consumption(&store), 1000.0);
+        // a damaged reefer unit drawing 900 instead of 5.0 per unit of weight
+        let extras = ContainerExtras { consumption_override: Some(900.0), ..Default::default() };
+        store.insert(2, ContainerData::Refrigerated { id: 2, weight: 100, extras });
+        assert_eq!(store[&1].total_consumption(), 500.0);
+        assert_eq!(store[&2].total_consumption(), 900.0);
+        assert_eq!(ship.container_consumption(&store), 1400.0);
+    }
+
+    #[test]
+    fn decommissioned_ship_leaves_its_cargo_in_port() {
+        let text = VOYAGE.replace("\n    ]}", ",\n        {\"decommissionship\": {\"ship_id\": 0}}\n    ]}");
+        let (state, out) = run_json(&text);
+        assert!(state.ships.is_empty());
+        assert_eq!(out["Port 1"]["ships"], serde_json::json!({}));
+        assert_eq!(out["Port 1"]["basic_container"], serde_json::json!([7]));
+    }
+
+    #[test]
+    fn fuel_percent_reads_the_tank_level() {
+        let mut ports = ports_at(&[(0.0, 0.0)]);
+        let mut ship = ship_at(0, 0, &mut ports);
+        assert_eq!(ship.fuel_percent(), 0.0);
+        ship.fuel = 1.0;
+        assert_eq!(ship.fuel_percent(), 100.0, "an unlimited tank with fuel in it reads full");
+        ship.max_fuel_capacity = 800.0;
+        ship.fuel = 400.0;
+        assert_eq!(ship.fuel_percent(), 50.0);
+        ship.max_fuel_capacity = 0.0;
+        assert_eq!(ship.fuel_percent(), 0.0);
+    }
+
+    // keeps every log record so tests can check what a run reported
+    struct CaptureLog;
+
+    static LOGGED: std::sync::Mutex<Vec<(log::Level, String)>> = std::sync::Mutex::new(Vec::new());
+
+    impl log::Log for CaptureLog {
+        fn enabled(&self, _: &log::Metadata) -> bool {
+            true
+        }
+        fn log(&self, record: &log::Record) {
+            LOGGED.lock().unwrap().push((record.level(), record.args().to_string()));
+        }
+        fn flush(&self) {}
+    }
+
+    #[test]
+    fn diagnostics_go_to_the_log_at_their_level() {
+        static CAPTURE: CaptureLog = CaptureLog;
+        // tests share one process, so the logger may already be in place
+        if log::set_logger(&CAPTURE).is_ok() {
+            log::set_max_level(log::LevelFilter::Trace);
+        }
+        let text = VOYAGE.replace("\n    ]}", ",\n        {\"createcontainer\": {\"id\": 354, \"weight\": 10, \"port_id\": 35}},\n        {\"load\": {\"ship_id\": 0, \"container_id\": 354}}\n    ]}");
+        run_json(&text);
+        let logged = LOGGED.lock().unwrap();
+        assert!(logged.contains(&(log::Level::Info, "Container 354 refers to unknown port 35; created a placeholder at 0,0".to_string())), "{:?}", logged);
+        assert!(logged.contains(&(log::Level::Warn, "Ship 0 could not load container 354: ContainerNotInPort".to_string())), "{:?}", logged);
+    }
+
+    #[test]
+    fn named_port_keys_the_output_and_names_stay_unique() {
+        let text = VOYAGE.replace("\n    ]}", r#",
+        {"renameport": {"port_id": 1, "name": "Rotterdam"}},
+        {"renameport": {"port_id": 0, "name": "Rotterdam"}},
+        {"renameport": {"port_id": 0, "name": "weight_histogram"}},
+        {"renameport": {"port_id": 0, "name": "warnings"}},
+        {"renameport": {"port_id": 0, "name": "Port 1"}},
+        {"createport": {"id": 2, "latitude": 1.0, "longitude": 1.0, "name": "weight_histogram"}}
+    ]}"#);
+        let (state, out) = run_json(&text);
+        assert_eq!(out["Rotterdam"]["ships"]["ship_0"]["basic_container"], serde_json::json!([7]));
+        assert!(out.get("Port 1").is_none());
+        assert_eq!(out["Port 0"]["basic_container"], serde_json::json!([]));
+        assert_eq!(state.ports[&0].name, None);
+        assert!(!state.ports.contains_key(&2));
+        let renames = &state.summary.operations["RenamePort"];
+        assert_eq!((renames.total, renames.succeeded), (5, 1), "{:?}", state.warnings);
+    }
+
+    #[test]
+    fn best_fit_fills_capacity_greedy_wastes() {
+        for (strategy, expected) in [(LoadStrategy::Greedy, vec![1]), (LoadStrategy::BestFit, vec![2, 3])] {
+            let mut ports = ports_at(&[(0.0, 0.0)]);
+            let mut store = HashMap::new();
+            for (id, weight) in [(1, 60), (2, 50), (3, 50)] {
+                stock(id, weight, None, 0, &mut ports, &mut store);
+            }
+            let mut ship = ship_at(0, 0, &mut ports);
+            ship.total_weight_capacity = 100;
+            let mut loaded = ship.auto_load(strategy, false, &mut ports, &mut store);
+            loaded.sort_unstable();
+            assert_eq!(loaded, expected, "{:?}", strategy);
+        }
+    }
+
+    #[test]
+    fn yard_id_missing_from_the_store_is_a_warning() {
+        let mut input = parse_input(VOYAGE, InputFormat::Json).unwrap();
+        let mut state = run(&mut input, false, false).unwrap();
+        state.ports.get_mut(&0).unwrap().containers.insert(99);
+        let out = build_output(&mut state, &input);
+        assert_eq!(out["Port 0"]["basic_container"], serde_json::json!([]));
+        assert_eq!(out["warnings"], serde_json::json!(["container 99 in Port 0 is missing from the container store"]));
+    }
+
+    #[test]
+    fn timing_table_covers_every_executed_kind() {
+        let text = VOYAGE.replace("\n    ]}", r#",
+        {"range": {"ship_id": 0}},
+        {"unload": {"ship_id": 0, "container_id": 7}},
+        {"load": {"ship_id": 0, "container_id": 7}}
+    ]}"#);
+        let mut input = parse_input(&text, InputFormat::Json).unwrap();
+        let expected: BTreeMap<&str, usize> = input.operations.iter().fold(BTreeMap::new(), |mut counts, op| {
+            *counts.entry(op.kind()).or_insert(0) += 1;
+            counts
+        });
+        let state = run(&mut input, false, true).unwrap();
+        let counts: BTreeMap<&str, usize> = state.timings.iter().map(|(&kind, &(_, count))| (kind, count)).collect();
+        assert_eq!(counts, expected);
+        assert_eq!(counts["Load"], 2);
+        assert!(run(&mut parse_input(&text, InputFormat::Json).unwrap(), false, false).unwrap().timings.is_empty());
+    }
+
+    #[test]
+    fn offload_all_empties_the_ship_into_port() {
+        let mut ports = ports_at(&[(0.0, 0.0)]);
+        let mut store = HashMap::new();
+        let mut ship = ship_at(0, 0, &mut ports);
+        for id in [1, 2, 3] {
+            stock(id, 100, None, 0, &mut ports, &mut store);
+            ship.load(id, &mut ports, &mut store).unwrap();
+        }
+        assert!(ports[&0].containers.is_empty());
+        let mut offloaded = ship.offload_all(false, &mut ports, &mut store);
+        offloaded.sort_unstable();
+        assert_eq!(offloaded, vec![1, 2, 3]);
+        assert!(ship.containers.is_empty());
+        assert_eq!(ports[&0].containers, HashSet::from([1, 2, 3]));
+    }
+
+    // port 0 has a single berth, taken by ship 0; ports 1 and 2 lie close by, port 3 far away
+    fn congested_port(extra: &str) -> String {
+        format!(r#"{{"operations": [
+            {{"createport": {{"id": 0, "latitude": 0.0, "longitude": 0.0, "max_berths": 1}}}},
+            {{"createport": {{"id": 1, "latitude": 0.0, "longitude": 0.1}}}},
+            {{"createport": {{"id": 2, "latitude": 0.0, "longitude": -0.1}}}},
+            {{"createport": {{"id": 3, "latitude": 0.0, "longitude": 100.0}}}},
+            {{"createship": {{"id": 0, "port_id": 0, "total_weight_capacity": 100000, "max_number_of_all_containers": 5, "max_number_of_heavy_containers": 5, "max_number_of_refrigerated_containers": 5, "max_number_of_liquid_containers": 5, "fuel_consumption_per_km": 1.0}}}},
+            {{"createship": {{"id": 1, "port_id": 1, "total_weight_capacity": 100000, "max_number_of_all_containers": 5, "max_number_of_heavy_containers": 5, "max_number_of_refrigerated_containers": 5, "max_number_of_liquid_containers": 5, "fuel_consumption_per_km": 1.0}}}},
+            {{"createship": {{"id": 2, "port_id": 2, "total_weight_capacity": 100000, "max_number_of_all_containers": 5, "max_number_of_heavy_containers": 5, "max_number_of_refrigerated_containers": 5, "max_number_of_liquid_containers": 5, "fuel_consumption_per_km": 1.0}}}},
+            {}
+        ]}}"#, extra)
+    }
+
+    #[test]
+    fn sail_to_a_full_port_queues_instead_of_failing() {
+        let (state, _) = run_json(&congested_port(r#"
+            {"refuel": {"ship_id": 1, "amount": 1000.0}},
+            {"refuel": {"ship_id": 0, "amount": 100000.0}},
+            {"sail": {"ship_id": 1, "dest_port_id": 0}}"#));
+        assert_eq!(state.ships[&1].current_port, 1, "the berth is taken, so the ship stays put");
+        assert_eq!(state.ports[&0].waiting, vec![1]);
+        let (state, _) = run_json(&congested_port(r#"
+            {"refuel": {"ship_id": 1, "amount": 1000.0}},
+            {"refuel": {"ship_id": 0, "amount": 100000.0}},
+            {"sail": {"ship_id": 1, "dest_port_id": 0}},
+            {"sail": {"ship_id": 0, "dest_port_id": 3}}"#));
+        assert_eq!(state.ships[&1].current_port, 0, "the queued ship docks once the berth frees");
+        assert!(state.ports[&0].waiting.is_empty());
+    }
+
+    #[test]
+    fn heavy_ship_jumps_the_berth_queue() {
+        let (state, _) = run_json(&congested_port(r#"
+            {"createcontainer": {"id": 5, "weight": 50, "port_id": 2}},
+            {"load": {"ship_id": 2, "container_id": 5}},
+            {"refuel": {"ship_id": 1, "amount": 1000.0}},
+            {"refuel": {"ship_id": 2, "amount": 1000.0}},
+            {"refuel": {"ship_id": 0, "amount": 100000.0}},
+            {"sail": {"ship_id": 1, "dest_port_id": 0}},
+            {"sail": {"ship_id": 2, "dest_port_id": 0}},
+            {"sail": {"ship_id": 0, "dest_port_id": 3}}"#));
+        assert_eq!(state.ships[&0].current_port, 3);
+        assert_eq!(state.ships[&2].current_port, 0, "the loaded ship takes the berth");
+        assert_eq!(state.ships[&1].current_port, 1);
+        assert_eq!(state.ports[&0].waiting, vec![1]);
+    }
+
+    #[test]
+    fn ship_stuck_at_a_refuel_stop_still_frees_its_berth() {
+        // ship 0 can reach a fuel stop next door, but not port 3 even after refuelling there
+        let (state, _) = run_json(&congested_port(r#"
+            {"refuel": {"ship_id": 1, "amount": 1000.0}},
+            {"refuel": {"ship_id": 0, "amount": 20.0}},
+            {"sail": {"ship_id": 1, "dest_port_id": 0}},
+            {"sail": {"ship_id": 0, "dest_port_id": 3}}"#));
+        assert_ne!(state.ships[&0].current_port, 0);
+        assert_ne!(state.ships[&0].current_port, 3);
+        assert_eq!(state.ships[&1].current_port, 0);
+        assert!(state.ports[&0].waiting.is_empty());
+    }
+
+    #[test]
+    fn reweigh_past_capacity_warns_and_keeps_totals_honest() {
+        let text = VOYAGE.replace("\n    ]}", ",\n        {\"reweigh\": {\"container_id\": 7, \"weight\": 150}}\n    ]}");
+        let (state, out) = run_json(&text);
+        assert_eq!(
+            out["warnings"],
+            serde_json::json!(["OverweightAfterReweigh: ship 0 carries 150 over its capacity 100 after container 7 was reweighed"])
+        );
+        assert_eq!(state.container_store[&7].weight(), 150);
+        assert_eq!(state.ships[&0].current_total_weight(&state.container_store), 150);
+        assert_eq!(state.ships[&0].containers, vec![7]);
+    }
+
+    #[test]
+    fn reweigh_reclassifies_and_rechecks_the_heavy_limit() {
+        // a roomy ship allowed one heavy container, already carrying one, then a basic one turns heavy
+        let text = r#"{"operations": [
+        {"createport": {"id": 0, "latitude": 0.0, "longitude": 0.0}},
+        {"createship": {"id": 0, "port_id": 0, "total_weight_capacity": 100000, "max_number_of_all_containers": 5, "max_number_of_heavy_containers": 1, "max_number_of_refrigerated_containers": 1, "max_number_of_liquid_containers": 1, "fuel_consumption_per_km": 1.0}},
+        {"createcontainer": {"id": 1, "weight": 4000, "port_id": 0}},
+        {"createcontainer": {"id": 2, "weight": 2000, "port_id": 0}},
+        {"load": {"ship_id": 0, "container_id": 1}},
+        {"load": {"ship_id": 0, "container_id": 2}},
+        {"reweigh": {"container_id": 2, "weight": 3500}}
+    ]}"#;
+        let (state, out) = run_json(text);
+        assert!(matches!(state.container_store[&2], ContainerData::Heavy { weight: 3500, .. }));
+        assert_eq!(
+            out["warnings"],
+            serde_json::json!(["HeavyLimitAfterReweigh: ship 0 carries 2 heavy containers over its limit 1 after container 2 was reweighed"])
+        );
+        let (state, out) = run_json(&text.replace("\"weight\": 3500}}", "\"weight\": 3500}},\n        {\"reweigh\": {\"container_id\": 1, \"weight\": 3000}}"));
+        assert!(matches!(state.container_store[&1], ContainerData::Basic { weight: 3000, .. }));
+        assert_eq!(state.ships[&0].current_heavy_count(&state.container_store), 1);
+        assert_eq!(out["warnings"].as_array().unwrap().len(), 1, "only the first reweigh broke the limit");
+    }
+
+    #[test]
+    fn port_index_matches_brute_force() {
+        // a spread of ports from a fixed pattern, with repeated coordinates to exercise the id tie-break
+        let coords: Vec<(f64, f64)> = (0..40).map(|i| (((i * 37) % 120) as f64 - 60.0, ((i * 91) % 360) as f64 - 180.0)).collect();
+        let ports = ports_at(&coords);
+        let mut index = PortIndex::build(&ports);
+        for from in ports.values() {
+            let mut brute: Vec<(usize, f64)> = ports.values().filter(|p| p.id != from.id).map(|p| (p.id, from.get_distance(p))).collect();
+            brute.sort_by(|a, b| a.1.total_cmp(&b.1).then(a.0.cmp(&b.0)));
+            for k in [0, 1, 5, 39, 100] {
+                assert_eq!(index.nearest(&ports, from.id, k), &brute[..k.min(brute.len())]);
+            }
+        }
+        assert!(index.nearest(&ports, 99, 3).is_empty());
+        assert_eq!(index.rebuilds, 1);
+    }
+
+    #[test]
+    fn port_index_is_rebuilt_only_when_a_lookup_needs_it() {
+        // port 1 starts far away and is re-created next door; with a stale index port 2 would look nearer
+        let text = r#"{"operations": [
+            {"createport": {"id": 0, "latitude": 0.0, "longitude": 0.0}},
+            {"createport": {"id": 1, "latitude": 0.0, "longitude": 50.0}},
+            {"createport": {"id": 2, "latitude": 0.0, "longitude": 0.2}},
+            {"createport": {"id": 3, "latitude": 0.0, "longitude": 1.0}},
+            {"createship": {"id": 0, "port_id": 0, "total_weight_capacity": 100, "max_number_of_all_containers": 5, "max_number_of_heavy_containers": 1, "max_number_of_refrigerated_containers": 1, "max_number_of_liquid_containers": 1, "fuel_consumption_per_km": 10.0}},
+            {"range": {"ship_id": 0}},
+            {"createport": {"id": 1, "latitude": 0.0, "longitude": 0.1}},
+            {"refuel": {"ship_id": 0, "amount": 500.0}},
+            {"sail": {"ship_id": 0, "dest_port_id": 3}}
+        ]}"#;
+        let (state, _) = run_json(text);
+        assert_eq!(state.ships[&0].current_port, 3);
+        assert!(state.ports[&1].history.contains(&0), "the refuel stop is the moved port");
+        assert!(!state.ports[&2].history.contains(&0));
+        assert_eq!(state.port_index.rebuilds, 1, "the CreatePort ops only mark the index; the fallback builds it once");
+
+        // a voyage that never needs a refuel stop never builds the index at all
+        let (state, _) = run_json(VOYAGE);
+        assert_eq!(state.port_index.rebuilds, 0);
+
+        let mut ports = ports_at(&[(0.0, 0.0), (0.0, 50.0), (0.0, 0.2)]);
+        let mut index = PortIndex::default();
+        index.nearest(&ports, 0, 1);
+        index.nearest(&ports, 1, 1);
+        assert_eq!(index.rebuilds, 1);
+        ports.insert(1, Port::new(1, 0.0, 0.1));
+        index.invalidate();
+        index.invalidate();
+        assert_eq!(index.nearest(&ports, 0, 1)[0].0, 1);
+        assert_eq!(index.rebuilds, 2);
+    }
+
+    #[test]
+    fn cloned_ship_shares_capacities_not_cargo() {
+        let text = VOYAGE.replace("\n    ]}", r#",
+        {"cloneship": {"source_id": 0, "new_id": 1, "port_id": 0}},
+        {"createcontainer": {"id": 8, "weight": 20, "port_id": 0}},
+        {"load": {"ship_id": 1, "container_id": 8}},
+        {"cloneship": {"source_id": 0, "new_id": 1, "port_id": 0}}
+    ]}"#);
+        let (state, _) = run_json(&text);
+        let (source, clone) = (&state.ships[&0], &state.ships[&1]);
+        let limits = |s: &Ship| (s.total_weight_capacity, s.max_number_of_all_containers, s.max_number_of_heavy_containers, s.max_number_of_refrigerated_containers, s.max_number_of_liquid_containers);
+        assert_eq!(limits(source), limits(clone));
+        assert_eq!(source.fuel_consumption_per_km, clone.fuel_consumption_per_km);
+        assert_eq!((clone.current_port, clone.fuel), (0, 0.0));
+        assert_eq!(source.containers, vec![7]);
+        assert_eq!(clone.containers, vec![8], "the second clone hits a taken id and leaves ship 1 alone");
+        assert_eq!(state.ports[&0].current, HashSet::from([1]));
+        let clones = &state.summary.operations["CloneShip"];
+        assert_eq!((clones.total, clones.succeeded), (2, 1));
+    }
+
+    #[test]
+    fn yard_weight_sums_the_port_containers() {
+        let mut ports = ports_at(&[(0.0, 0.0), (1.0, 1.0)]);
+        let mut store = HashMap::new();
+        stock(1, 1200, None, 0, &mut ports, &mut store);
+        stock(2, 4000, None, 0, &mut ports, &mut store);
+        stock(3, 800, Some("L"), 0, &mut ports, &mut store);
+        stock(4, 999, None, 1, &mut ports, &mut store);
+        assert_eq!(ports[&0].total_container_weight(&store), 6000);
+        // ids missing from the store add nothing
+        ports.get_mut(&0).unwrap().containers.insert(42);
+        assert_eq!(ports[&0].total_container_weight(&store), 6000);
+    }
+
+    #[test]
+    fn fail_fast_stops_at_an_impossible_load() {
+        // the ship carries at most 100, the container weighs 500
+        let text = VOYAGE.replace(r#""weight": 10"#, r#""weight": 500"#);
+        let mut input = parse_input(&text, InputFormat::Json).unwrap();
+        let err = run(&mut input, true, false).err().unwrap();
+        assert_eq!(err, "operation 5 (Load) failed: Ship 0 could not load container 7: WeightCapacityExceeded");
+
+        let (state, _) = run_json(&text);
+        assert_eq!(state.ships[&0].current_port, 1, "without the flag the run carries on");
+        assert!(state.ships[&0].containers.is_empty());
+        assert!(state.ports[&0].containers.contains(&7));
+        let loads = &state.summary.operations["Load"];
+        assert_eq!((loads.total, loads.succeeded), (1, 0));
+    }
+
+    #[test]
+    fn find_containers_filters_by_kind_and_weight() {
+        let mut ports = ports_at(&[(0.0, 0.0)]);
+        let mut store = HashMap::new();
+        for (id, weight, special) in [(1, 2500, Some("R")), (2, 1500, Some("R")), (3, 2500, None), (4, 4000, Some("R")), (5, 3000, Some("L"))] {
+            stock(id, weight, special, 0, &mut ports, &mut store);
+        }
+        let reefers_over_2000 = find_containers(&store, |c| c.kind_name() == "refrigerated" && c.weight() > 2000);
+        assert_eq!(reefers_over_2000, vec![1, 4]);
+        assert_eq!(find_containers(&store, |c| (2000..=3000).contains(&c.weight())), vec![1, 3, 5]);
+        assert!(find_containers(&store, |c| c.kind_name() == "heavy").is_empty());
+    }
+
+    #[test]
+    fn negative_zero_longitude_prints_as_zero() {
+        let (_, out) = run_json(r#"{"operations": [
+            {"createport": {"id": 0, "latitude": 48.86000000000001, "longitude": -0.0}},
+            {"createport": {"id": 1, "latitude": -0.001, "longitude": 2.3449999}}
+        ]}"#);
+        assert_eq!(serde_json::to_string(&out["Port 0"]["lon"]).unwrap(), "0.0");
+        assert_eq!(serde_json::to_string(&out["Port 0"]["lat"]).unwrap(), "48.86");
+        assert_eq!(serde_json::to_string(&out["Port 1"]["lat"]).unwrap(), "0.0");
+        assert_eq!(serde_json::to_string(&out["Port 1"]["lon"]).unwrap(), "2.34");
+    }
+
+    #[test]
+    fn second_file_uses_what_the_first_created() {
+        let base = format!(r#"{{"operations": [
+            {{"createport": {{"id": 0, "latitude": 0.0, "longitude": 0.0}}}},
+            {{"createport": {{"id": 1, "latitude": 0.0, "longitude": 1.0}}}},
+            {},
+            {{"createcontainer": {{"id": 7, "weight": 10, "port_id": 0}}}}
+        ]}}"#, createship_json(0, 0));
+        let voyage = r#"{"weight_histogram": [100], "operations": [
+            {"refuel": {"ship_id": 0, "amount": 1000.0}},
+            {"load": {"ship_id": 0, "container_id": 7}},
+            {"sail": {"ship_id": 0, "dest_port_id": 1}}
+        ]}"#;
+        let mut input = parse_input(&base, InputFormat::Json).unwrap();
+        input.merge(parse_input(voyage, InputFormat::Json).unwrap());
+        assert_eq!(input.weight_histogram, Some(vec![100]));
+        let mut state = run(&mut input, true, false).unwrap();
+        let out = build_output(&mut state, &input);
+        assert_eq!(out["Port 1"]["ships"]["ship_0"]["basic_container"], serde_json::json!([7]));
+        assert!(out.get("weight_histogram").is_some());
+    }
+
+    #[test]
+    fn cargo_value_mixes_declared_and_derived_values() {
+        let mut ports = ports_at(&[(0.0, 0.0)]);
+        let mut store = HashMap::new();
+        let mut ship = ship_at(0, 0, &mut ports);
+        stock(1, 400, None, 0, &mut ports, &mut store);
+        stock(2, 300, Some("R"), 0, &mut ports, &mut store);
+        let extras = ContainerExtras { value: Some(5000.0), ..Default::default() };
+        store.insert(2, ContainerData::Refrigerated { id: 2, weight: 300, extras });
+        stock(3, 900, None, 0, &mut ports, &mut store);
+        for id in [1, 2] {
+            ship.load(id, &mut ports, &mut store).unwrap();
+        }
+        // 400 derived from weight plus 5000 declared; the container left in the yard doesn't count
+        assert_eq!(ship.cargo_value(&store), 400.0 * DEFAULT_VALUE_PER_WEIGHT + 5000.0);
+    }
+
+    #[test]
+    fn storm_on_the_direct_leg_sends_the_ship_round_it() {
+        let scenario = |storm: &str| format!(r#"{{"operations": [
+            {{"createport": {{"id": 0, "latitude": 0.0, "longitude": 0.0}}}},
+            {{"createport": {{"id": 1, "latitude": 0.2, "longitude": 0.5}}}},
+            {{"createport": {{"id": 2, "latitude": 0.0, "longitude": 1.0}}}},
+            {},
+            {{"refuel": {{"ship_id": 0, "amount": 150.0}}}},
+            {}
+            {{"sail": {{"ship_id": 0, "dest_port_id": 2}}}}
+        ]}}"#, createship_json(0, 0), storm);
+        let calm = run_json(&scenario("")).0;
+        assert_eq!(calm.ships[&0].current_port, 2);
+        assert!(calm.ships[&0].fuel < 150.0);
+        let stormy = run_json(&scenario(r#"{"setrouteweather": {"from_port": 0, "to_port": 2, "multiplier": 10.0}},"#)).0;
+        // only the detour tops up the tank, at the calm stop in between
+        assert_eq!(stormy.ships[&0].current_port, 2);
+        assert!(stormy.ships[&0].fuel > REFUEL_AMOUNT);
+    }
+
+    #[test]
+    fn sailing_to_the_current_port_changes_nothing() {
+        let mut ports = ports_at(&[(0.0, 0.0)]);
+        let mut ship = ship_at(0, 0, &mut ports);
+        ship.fuel = 50.0;
+        let before = ports[&0].clone();
+        assert_eq!(ship.sail_to(0, &mut ports, &HashMap::new(), 1.0), Err(ShipOpError::AlreadyThere));
+        assert_eq!(ship.fuel, 50.0);
+        let after = &ports[&0];
+        assert_eq!((&after.current, &after.history), (&before.current, &before.history));
+    }
+
+    #[test]
+    fn laden_voyage_gets_fewer_km_per_fuel_than_nominal() {
+        let (state, out) = run_json(VOYAGE);
+        let ship = &state.ships[&0];
+        let nominal = 1.0 / ship.fuel_consumption_per_km;
+        let realized = ship.km_per_fuel().unwrap();
+        assert!(realized < nominal, "realized {} vs nominal {}", realized, nominal);
+        assert_eq!(out["Port 1"]["ships"]["ship_0"]["km_per_fuel"], serde_json::json!(output_number(realized)));
+
+        let mut ports = ports_at(&[(0.0, 0.0)]);
+        assert_eq!(ship_at(1, 0, &mut ports).km_per_fuel(), None, "nothing burned yet");
+    }
+
+    #[test]
+    fn fuel_transfer_is_clamped_to_what_the_donor_has() {
+        let text = format!(r#"{{"operations": [
+            {{"createport": {{"id": 0, "latitude": 0.0, "longitude": 0.0}}}},
+            {}, {}, {},
+            {{"refuel": {{"ship_id": 0, "amount": 30.0}}}},
+            {{"transferfuel": {{"from_ship": 0, "to_ship": 1, "amount": 100.0}}}},
+            {{"refuel": {{"ship_id": 1, "amount": 10.0}}}},
+            {{"transferfuel": {{"from_ship": 1, "to_ship": 2, "amount": 100.0}}}}
+        ]}}"#, createship_json(0, 0), createship_json(1, 0), createship_json(2, 0).replace("}}", r#", "max_fuel_capacity": 25.0}}"#));
+        let (state, _) = run_json(&text);
+        let fuel = |id: usize| state.ships[&id].fuel;
+        // 30 moved from ship 0 although 100 was asked; then ship 2's tank only takes 25 of ship 1's 40
+        assert_eq!((fuel(0), fuel(1), fuel(2)), (0.0, 15.0, 25.0));
+    }
+
+    #[test]
+    fn load_then_unload_restores_the_world() {
+        let mut ports = ports_at(&[(0.0, 0.0)]);
+        let mut store = HashMap::new();
+        // a fixed spread of kinds and weights, heavy ones included
+        for id in 0..30 {
+            let special = [None, Some("R"), Some("L")][id % 3];
+            stock(id, 500 * (1 + (id * 7 % 10)) as i32, special, 0, &mut ports, &mut store);
+        }
+        let mut ship = ship_at(0, 0, &mut ports);
+        ship.stacking = StackMode::Lifo;
+        // some cargo already aboard, so each round trip happens on top of a stack
+        for id in 0..5 {
+            ship.load(id, &mut ports, &mut store).unwrap();
+        }
+        let counts = |s: &Ship, store: &HashMap<usize, ContainerData>| {
+            (s.current_total_weight(store), s.current_heavy_count(store), s.current_refrigerated_count(store), s.current_liquid_count(store))
+        };
+        for id in 5..30 {
+            let (yard, cargo, before) = (ports[&0].containers.clone(), ship.containers.clone(), counts(&ship, &store));
+            ship.load(id, &mut ports, &mut store).unwrap();
+            ship.un_load(id, &mut ports, &mut store).unwrap();
+            assert_eq!(ports[&0].containers, yard);
+            assert_eq!(ship.containers, cargo);
+            assert_eq!(counts(&ship, &store), before);
+        }
+        // a yard id the store doesn't know is refused rather than panicking
+        ports.get_mut(&0).unwrap().containers.insert(99);
+        assert_eq!(ship.load(99, &mut ports, &mut store), Err(ShipOpError::UnknownContainer));
+        assert_eq!(ship.containers, vec![0, 1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn liquid_weight_cap_stops_loading_before_other_limits() {
+        let mut ports = ports_at(&[(0.0, 0.0)]);
+        let mut store = HashMap::new();
+        let mut ship = ship_at(0, 0, &mut ports);
+        ship.max_liquid_weight = 1000;
+        for (id, weight) in [(1, 400), (2, 600), (3, 100)] {
+            stock(id, weight, Some("L"), 0, &mut ports, &mut store);
+        }
+        stock(4, 100, None, 0, &mut ports, &mut store);
+        ship.load(1, &mut ports, &mut store).unwrap();
+        ship.load(2, &mut ports, &mut store).unwrap();
+        assert_eq!(ship.load(3, &mut ports, &mut store), Err(ShipOpError::LiquidWeightExceeded));
+        assert!(ports[&0].containers.contains(&3));
+        ship.load(4, &mut ports, &mut store).unwrap();
+    }
+
+    #[test]
+    fn upsert_updates_capacities_and_keeps_the_cargo() {
+        let text = VOYAGE.replace("\n    ]}", r#",
+        {"createship": {"id": 0, "port_id": 0, "total_weight_capacity": 500, "max_number_of_all_containers": 8, "max_number_of_heavy_containers": 2, "max_number_of_refrigerated_containers": 2, "max_number_of_liquid_containers": 2, "fuel_consumption_per_km": 2.0, "upsert": true}}
+    ]}"#);
+        let before = run_json(VOYAGE).0.ships.remove(&0).unwrap();
+        let (state, _) = run_json(&text);
+        let ship = &state.ships[&0];
+        assert_eq!((ship.total_weight_capacity, ship.max_number_of_all_containers, ship.fuel_consumption_per_km), (500, 8, 2.0));
+        assert_eq!(ship.containers, vec![7]);
+        assert_eq!((ship.current_port, ship.fuel), (before.current_port, before.fuel));
+        assert!(state.ports[&1].current.contains(&0) && !state.ports[&0].current.contains(&0));
+    }
+
+    #[test]
+    fn bounding_box_spans_every_port() {
+        assert_eq!(ports_bounding_box(&HashMap::new()), None);
+        assert_eq!(ports_bounding_box(&ports_at(&[(12.5, -3.0)])), Some((12.5, 12.5, -3.0, -3.0)));
+        let ports = ports_at(&[(10.0, 20.0), (-5.0, 40.0), (30.0, -170.0), (0.0, 0.0)]);
+        assert_eq!(ports_bounding_box(&ports), Some((-5.0, 30.0, -170.0, 40.0)));
+    }
+
+    #[test]
+    fn transfers_stop_at_the_all_container_limit() {
+        let ship = |id: usize, all: usize| format!(r#"{{"createship": {{"id": {}, "port_id": 0, "total_weight_capacity": 1000, "max_number_of_all_containers": {}, "max_number_of_heavy_containers": 0, "max_number_of_refrigerated_containers": 0, "max_number_of_liquid_containers": 0, "fuel_consumption_per_km": 1.0}}}}"#, id, all);
+        let mut ops = vec![r#"{"createport": {"id": 0, "latitude": 0.0, "longitude": 0.0}}"#.to_string(), ship(0, 5), ship(1, 2)];
+        for id in 1..=3 {
+            ops.push(format!(r#"{{"createcontainer": {{"id": {}, "weight": 10, "port_id": 0}}}}"#, id));
+            ops.push(format!(r#"{{"load": {{"ship_id": 0, "container_id": {}}}}}"#, id));
+        }
+        for id in 1..=3 {
+            ops.push(format!(r#"{{"transfer": {{"from_ship": 0, "to_ship": 1, "container_id": {}}}}}"#, id));
+        }
+        let text = format!(r#"{{"operations": [{}]}}"#, ops.join(",\n"));
+
+        let mut input = parse_input(&text, InputFormat::Json).unwrap();
+        let err = run(&mut input, true, false).err().unwrap();
+        assert_eq!(err, "operation 11 (Transfer) failed: Ship 1 could not take container 3 from ship 0: ContainerLimitExceeded");
+        // without --fail-fast the refused container goes back onto the donor
+        let (state, _) = run_json(&text);
+        assert_eq!(state.ships[&1].containers, vec![1, 2]);
+        assert_eq!(state.ships[&0].containers, vec![3]);
+        assert!(state.ports[&0].containers.is_empty());
+    }
+
+    #[test]
+    fn undo_puts_a_loaded_container_back_in_port() {
+        let text = VOYAGE.replace(r#",
+        {"sail": {"ship_id": 0, "dest_port_id": 1}}"#, r#",
+        "undo",
+        "undo""#);
+        let (state, out) = run_json(&text);
+        assert!(state.ships[&0].containers.is_empty());
+        assert_eq!(out["Port 0"]["basic_container"], serde_json::json!([7]));
+        assert_eq!(state.ships[&0].fuel, 0.0, "the second undo reverted the refuel");
+        let undos = &state.summary.operations["Undo"];
+        assert_eq!((undos.total, undos.succeeded), (2, 2));
+    }
+
+    #[test]
+    fn flat_reefer_draw_is_charged_on_the_voyage() {
+        let mut ports = ports_at(&[(0.0, 0.0), (0.0, 0.1)]);
+        let mut store = HashMap::new();
+        let mut ship = ship_at(0, 0, &mut ports);
+        // 10 units at 5.0 each, dwarfed by a flat 400
+        stock(1, 10, Some("R"), 0, &mut ports, &mut store);
+        let extras = ContainerExtras { flat_consumption: 400.0, ..Default::default() };
+        store.insert(1, ContainerData::Refrigerated { id: 1, weight: 10, extras });
+        ship.load(1, &mut ports, &mut store).unwrap();
+        assert_eq!(store[&1].total_consumption(), 450.0);
+        let distance = ports[&0].get_distance(&ports[&1]);
+        ship.fuel = 1000.0;
+        ship.sail_to(1, &mut ports, &store, 1.0).unwrap();
+        assert!((ship.fuel - (1000.0 - distance - 450.0)).abs() < 1e-9);
+    }
+
+    #[test]
+    fn ships_carrying_lists_only_ships_with_that_kind() {
+        let mut ports = ports_at(&[(0.0, 0.0)]);
+        let mut store = HashMap::new();
+        let mut fleet: Vec<Ship> = (0..3).map(|id| ship_at(id, 0, &mut ports)).collect();
+        let cargo: [(usize, Option<&str>, usize); 5] = [(1, Some("R"), 0), (2, Some("R"), 0), (3, None, 0), (4, Some("L"), 1), (5, Some("R"), 2)];
+        for (id, special, ship) in cargo {
+            stock(id, 100, special, 0, &mut ports, &mut store);
+            fleet[ship].load(id, &mut ports, &mut store).unwrap();
+        }
+        let ships: HashMap<usize, Ship> = fleet.into_iter().map(|s| (s.id, s)).collect();
+        assert_eq!(ships_carrying(&ships, &store, "refrigerated"), vec![(0, 2), (2, 1)]);
+        assert_eq!(ships_carrying(&ships, &store, "liquid"), vec![(1, 1)]);
+        assert!(ships_carrying(&ships, &store, "heavy").is_empty());
+    }
+
+    #[test]
+    fn empty_scenarios_produce_an_empty_world() {
+        for text in ["{}", r#"{"operations": []}"#] {
+            let (state, out) = run_json(text);
+            assert!(state.ports.is_empty() && state.ships.is_empty());
+            assert_eq!(out, serde_json::json!({}), "{}", text);
+        }
+    }
+
+    #[test]
+    fn refuel_stop_skips_a_port_without_fuel() {
+        let (state, _) = run_json(&format!(r#"{{"operations": [
+            {{"createport": {{"id": 0, "latitude": 0.0, "longitude": 0.0}}}},
+            {{"createport": {{"id": 1, "latitude": 0.0, "longitude": 0.3, "has_fuel": false}}}},
+            {{"createport": {{"id": 2, "latitude": 0.0, "longitude": 0.5}}}},
+            {{"createport": {{"id": 3, "latitude": 0.0, "longitude": 1.0}}}},
+            {},
+            {{"refuel": {{"ship_id": 0, "amount": 80.0}}}},
+            {{"sail": {{"ship_id": 0, "dest_port_id": 3}}}},
+            {},
+            {{"refuel": {{"ship_id": 1, "amount": 80.0}}}}
+        ]}}"#, createship_json(0, 0), createship_json(1, 1)));
+        // ship 0 passed through port 2, not the nearer fuelless port 1
+        assert_eq!(state.ships[&0].current_port, 3);
+        assert!(state.ports[&2].history.contains(&0) && !state.ports[&1].history.contains(&0));
+        assert_eq!(state.ships[&1].fuel, 0.0, "port 1 sells no fuel");
+    }
+
+    #[test]
+    fn cargo_centroid_leans_towards_the_heavier_yard() {
+        let mut ports = ports_at(&[(0.0, 0.0), (10.0, 20.0), (50.0, 50.0)]);
+        let mut store = HashMap::new();
+        assert_eq!(cargo_centroid(&ports, &store), None);
+        stock(1, 1000, None, 0, &mut ports, &mut store);
+        stock(2, 2000, None, 1, &mut ports, &mut store);
+        stock(3, 1000, None, 1, &mut ports, &mut store);
+        // three quarters of the weight sits at port 1; the empty port 2 weighs nothing
+        let (lat, lon) = cargo_centroid(&ports, &store).unwrap();
+        assert!((lat - 7.5).abs() < 1e-9 && (lon - 15.0).abs() < 1e-9, "({}, {})", lat, lon);
+    }
+
+    #[test]
+    fn out_of_service_ship_stays_until_it_returns() {
+        let sail = r#"{"sail": {"ship_id": 0, "dest_port_id": 1}}"#;
+        let off = r#"{"setshipservice": {"ship_id": 0, "in_service": false}}"#;
+        let on = r#"{"setshipservice": {"ship_id": 0, "in_service": true}}"#;
+        let (state, out) = run_json(&VOYAGE.replace(sail, &format!("{off}, {sail}")));
+        assert_eq!(state.ships[&0].current_port, 0);
+        assert_eq!(out["Port 0"]["ships"]["ship_0"]["basic_container"], serde_json::json!([7]), "still listed while out of service");
+        let sails = &state.summary.operations["Sail"];
+        assert_eq!((sails.total, sails.succeeded), (1, 0));
+
+        let (state, _) = run_json(&VOYAGE.replace(sail, &format!("{off}, {sail}, {on}, {sail}")));
+        assert_eq!(state.ships[&0].current_port, 1);
+
+        // decommissioning is refused up front rather than half-offloading the cargo
+        let decommission = r#"{"decommissionship": {"ship_id": 0}}"#;
+        let mut input = parse_input(&VOYAGE.replace(sail, &format!("{off}, {decommission}")), InputFormat::Json).unwrap();
+        let err = run(&mut input, true, false).err().unwrap();
+        assert_eq!(err, "operation 7 (DecommissionShip) failed: Ship 0 could not be decommissioned: ShipOutOfService");
+        let (state, _) = run_json(&VOYAGE.replace(sail, &format!("{off}, {decommission}")));
+        assert_eq!(state.ships[&0].containers, vec![7]);
+    }
+
+    #[test]
+    fn evacuation_moves_reachable_ships_and_strands_the_rest() {
+        let (state, _) = run_json(&format!(r#"{{"operations": [
+            {{"createport": {{"id": 0, "latitude": 0.0, "longitude": 0.0, "has_fuel": false}}}},
+            {{"createport": {{"id": 1, "latitude": 0.0, "longitude": 0.5}}}},
+            {{"createport": {{"id": 2, "latitude": 0.0, "longitude": 5.0, "has_fuel": false}}}},
+            {}, {}, {},
+            {{"refuel": {{"ship_id": 0, "amount": 150.0}}}},
+            {{"sail": {{"ship_id": 0, "dest_port_id": 0}}}},
+            "evacuatetofuel"
+        ]}}"#, createship_json(0, 1), createship_json(1, 1), createship_json(2, 2)));
+        let at = |id: usize| state.ships[&id].current_port;
+        // ship 0 sailed out to the fuelless port 0 and can make it back; ship 1 is already where fuel
+        // is sold; ship 2 has an empty tank 500 km from the nearest fuel
+        assert_eq!((at(0), at(1), at(2)), (1, 1, 2));
+    }
+
+    #[test]
+    fn same_seed_generates_the_same_scenario() {
+        let text = |seed: u64| serde_json::to_string(&generate_scenario(seed, GEN_PORTS, GEN_SHIPS, GEN_CONTAINERS, GEN_OPS)).unwrap();
+        assert_eq!(text(397), text(397));
+        assert_ne!(text(397), text(398));
+        assert_eq!(run_json(&text(397)).1, run_json(&text(397)).1);
+    }
+
+    #[test]
+    fn can_load_predicts_what_load_does() {
+        let mut ports = ports_at(&[(0.0, 0.0), (1.0, 1.0)]);
+        let mut store = HashMap::new();
+        let mut ship = ship_at(0, 0, &mut ports);
+        ship.total_weight_capacity = 5000;
+        ship.max_number_of_refrigerated_containers = 1;
+        let cargo: [(usize, i32, Option<&str>, usize); 6] = [(1, 1000, Some("R"), 0), (2, 1000, Some("R"), 0), (3, 4500, None, 0), (4, 500, None, 1), (5, 2000, Some("L"), 0), (6, 2500, None, 0)];
+        for (id, weight, special, port) in cargo {
+            stock(id, weight, special, port, &mut ports, &mut store);
+        }
+        let mut outcomes = Vec::new();
+        for id in [1, 2, 3, 4, 5, 6, 99] {
+            let predicted = ship.can_load(id, &ports, &store);
+            assert_eq!(ship.load(id, &mut ports, &mut store), predicted, "container {}", id);
+            outcomes.push(predicted);
+        }
+        use ShipOpError::*;
+        assert_eq!(outcomes, vec![Ok(()), Err(RefrigeratedLimitExceeded), Err(WeightCapacityExceeded), Err(ContainerNotInPort), Ok(()), Err(WeightCapacityExceeded), Err(ContainerNotInPort)]);
+    }
+
+    #[test]
+    fn summary_counts_operations_by_kind() {
+        let text = VOYAGE.replace("\n    ]}", r#",
+        {"load": {"ship_id": 0, "container_id": 99}},
+        {"sail": {"ship_id": 5, "dest_port_id": 0}}
+    ]}"#);
+        let (state, _) = run_json(&text);
+        let counts: Vec<(&str, usize, usize)> = state.summary.operations.iter().map(|(k, c)| (*k, c.total, c.succeeded)).collect();
+        assert_eq!(counts, vec![("CreateContainer", 1, 1), ("CreatePort", 2, 2), ("CreateShip", 1, 1), ("Load", 2, 1), ("Refuel", 1, 1), ("Sail", 2, 1)]);
+    }
+
+    #[test]
+    fn double_fuel_density_doubles_the_burn() {
+        let burned = |density: &str| {
+            let text = VOYAGE.replace(r#"{"operations""#, &format!(r#"{{"fuel_density": {}, "operations""#, density));
+            let (state, _) = run_json(&text);
+            state.ships[&0].fuel_burned
+        };
+        let nominal = burned("1.0");
+        assert!(nominal > 0.0);
+        assert!((burned("2.0") - 2.0 * nominal).abs() < 1e-9);
+    }
+
+    #[test]
+    fn loaded_container_travels_with_its_ship() {
+        let (_, out) = run_json(VOYAGE);
+        assert_eq!(out["Port 1"]["ships"]["ship_0"]["basic_container"], serde_json::json!([7]));
+        assert_eq!(out["Port 0"]["basic_container"], serde_json::json!([]));
+        assert_eq!(out["Port 0"]["ships"], serde_json::json!({}));
+        assert_eq!(out["Port 1"]["basic_container"], serde_json::json!([]));
+    }
+
+    #[test]
+    fn equal_weights_order_by_id_under_the_weight_sort() {
+        let containers: Vec<String> = [(9, 100), (3, 100), (2, 200), (7, 100), (5, 50), (1, 100)].iter()
+            .map(|(id, w)| format!(r#"{{"createcontainer": {{"id": {}, "weight": {}, "port_id": 0}}}}"#, id, w))
+            .collect();
+        let text = format!(r#"{{"output_sort": "byweightthenid", "operations": [
+            {{"createport": {{"id": 0, "latitude": 0.0, "longitude": 0.0}}}},
+            {}
+        ]}}"#, containers.join(",\n"));
+        for _ in 0..10 {
+            assert_eq!(run_json(&text).1["Port 0"]["basic_container"], serde_json::json!([5, 1, 3, 7, 9, 2]));
+        }
+        assert_eq!(run_json(&text.replace("byweightthenid", "byid")).1["Port 0"]["basic_container"], serde_json::json!([1, 2, 3, 5, 7, 9]));
+    }
+
+    #[test]
+    fn container_counts_reconcile_after_moves() {
+        let text = VOYAGE.replace("\n    ]}", r#",
+        {"createcontainer": {"id": 8, "weight": 10, "port_id": 1}},
+        {"createcontainer": {"id": 9, "weight": 10, "port_id": 1}},
+        {"load": {"ship_id": 0, "container_id": 8}},
+        {"load": {"ship_id": 0, "container_id": 9}},
+        {"unload": {"ship_id": 0, "container_id": 7}},
+        "countcontainers"
+    ]}"#);
+        let (state, _) = run_json(&text);
+        assert_eq!(count_containers(&state.ports, &state.ships, &state.container_store), (1, 2, 0));
+        let counts = &state.summary.operations["CountContainers"];
+        assert_eq!((counts.total, counts.succeeded), (1, 1));
+    }
+
+    #[test]
+    fn liquids_only_ship_refuses_other_kinds() {
+        let mut ports = ports_at(&[(0.0, 0.0)]);
+        let mut store = HashMap::new();
+        let mut tanker = ship_at(0, 0, &mut ports);
+        tanker.allowed_kinds = Some(vec!["liquid".to_string()]);
+        stock(1, 500, Some("L"), 0, &mut ports, &mut store);
+        stock(2, 500, None, 0, &mut ports, &mut store);
+        stock(3, 500, Some("R"), 0, &mut ports, &mut store);
+        tanker.load(1, &mut ports, &mut store).unwrap();
+        assert_eq!(tanker.load(2, &mut ports, &mut store), Err(ShipOpError::KindNotAllowed));
+        assert_eq!(tanker.load(3, &mut ports, &mut store), Err(ShipOpError::KindNotAllowed));
+        assert_eq!(tanker.containers, vec![1]);
+    }
+
+    #[test]
+    fn fleet_utilization_averages_known_loads() {
+        let mut ports = ports_at(&[(0.0, 0.0)]);
+        let mut store = HashMap::new();
+        let mut a = ship_at(0, 0, &mut ports);
+        let mut b = ship_at(1, 0, &mut ports);
+        let mut c = ship_at(2, 0, &mut ports);
+        (a.total_weight_capacity, a.max_number_of_all_containers) = (1000, 4);
+        (b.total_weight_capacity, b.max_number_of_all_containers) = (400, 4);
+        (c.total_weight_capacity, c.max_number_of_all_containers) = (0, 0);
+        stock(1, 250, None, 0, &mut ports, &mut store);
+        a.load(1, &mut ports, &mut store).unwrap();
+        for id in 2..=4 {
+            stock(id, 100, None, 0, &mut ports, &mut store);
+            b.load(id, &mut ports, &mut store).unwrap();
+        }
+        let ships = HashMap::from([(0, a), (1, b), (2, c)]);
+        let util = fleet_utilization(&ships, &store);
+        assert_eq!((util.per_ship[&0].weight_percent, util.per_ship[&0].count_percent), (Some(25.0), Some(25.0)));
+        assert_eq!((util.per_ship[&1].weight_percent, util.per_ship[&1].count_percent), (Some(75.0), Some(75.0)));
+        assert_eq!((util.per_ship[&2].weight_percent, util.per_ship[&2].count_percent), (None, None), "no capacity, no percentage");
+        assert_eq!((util.avg_weight_percent, util.avg_count_percent), (Some(50.0), Some(50.0)));
+    }
+
+    #[test]
+    fn cleared_history_only_keeps_later_visits() {
+        let ship = |id: usize| format!(r#"{},
+            {{"refuel": {{"ship_id": {id}, "amount": 1000.0}}}}"#, createship_json(id, 0));
+        let sail = |id: usize| format!(r#"{{"sail": {{"ship_id": {id}, "dest_port_id": 1}}}}"#);
+        let (state, _) = run_json(&format!(r#"{{"operations": [
+            {{"createport": {{"id": 0, "latitude": 0.0, "longitude": 0.0}}}},
+            {{"createport": {{"id": 1, "latitude": 0.0, "longitude": 0.1}}}},
+            {}, {}, {},
+            {}, {},
+            {{"clearhistory": {{"port_id": 1}}}},
+            {}
+        ]}}"#, ship(0), ship(1), ship(2), sail(0), sail(1), sail(2)));
+        assert_eq!(state.ports[&1].history, HashSet::from([2]));
+        assert_eq!(state.ports[&1].current, HashSet::from([0, 1, 2]), "docked ships stay docked");
+    }
+
+    #[test]
+    fn fuel_unit_labels_fuel_and_defaults_to_litres() {
+        let ship = |text: &str| run_json(text).1["Port 1"]["ships"]["ship_0"].clone();
+        assert_eq!(ship(VOYAGE)["fuel_unit"], serde_json::json!("L"));
+        let tonnes = ship(&VOYAGE.replace(r#"{"operations""#, r#"{"fuel_unit": "t", "operations""#));
+        assert_eq!(tonnes["fuel_unit"], serde_json::json!("t"));
+        assert_eq!(tonnes["fuel_left"], ship(VOYAGE)["fuel_left"], "the unit is only a label");
+    }
+
+    #[test]
+    fn auto_container_ids_ascend_past_manual_ones() {
+        let autos = r#"{"createcontainerauto": {"weight": 10, "port_id": 1}}"#;
+        let text = VOYAGE.replace("\n    ]}", &format!(",\n        {a},\n        {{\"createcontainer\": {{\"id\": 20, \"weight\": 10, \"port_id\": 1}}}},\n        {a},\n        {a}\n    ]}}", a = autos));
+        let (state, _) = run_json(&text);
+        let mut ids: Vec<usize> = state.container_store.keys().copied().collect();
+        ids.sort();
+        assert_eq!(ids, vec![7, 8, 20, 21, 22]);
+        assert_eq!(state.ports[&1].containers.len(), 4, "every auto container landed at its port");
+    }
+
+    #[test]
+    fn heavy_hold_past_i32_max_is_still_rejected() {
+        let mut ports = ports_at(&[(0.0, 0.0)]);
+        let mut store = HashMap::new();
+        for id in 0..3 {
+            stock(id, 1_000_000_000, None, 0, &mut ports, &mut store);
+        }
+        let mut ship = Ship::new(0, 0, i32::MAX, 40, 40, 40, 40, 1.0);
+        ports.get_mut(&0).unwrap().incoming_ship(0);
+        ship.load(0, &mut ports, &mut store).unwrap();
+        ship.load(1, &mut ports, &mut store).unwrap();
+        // 3e9 would wrap to a negative i32 total and slip past the capacity check
+        assert_eq!(ship.load(2, &mut ports, &mut store), Err(ShipOpError::WeightCapacityExceeded));
+        assert_eq!(ship.current_total_weight(&store), 2_000_000_000);
+    }
+
+    #[test]
+    fn five_ports_in_one_batch() {
+        let (state, _) = run_json(r#"{"operations": [{"createports": {"ports": [
+            {"id": 0, "latitude": 0.0, "longitude": 0.0},
+            {"id": 1, "latitude": 10.0, "longitude": -5.0, "name": "North"},
+            {"id": 2, "latitude": -20.5, "longitude": 30.0},
+            {"id": 3, "latitude": 45.0, "longitude": 90.0, "max_berths": 2},
+            {"id": 4, "latitude": -60.0, "longitude": -120.25, "has_fuel": false}
+        ]}}]}"#);
+        assert_eq!(state.ports.len(), 5);
+        let coords = [(0.0, 0.0), (10.0, -5.0), (-20.5, 30.0), (45.0, 90.0), (-60.0, -120.25)];
+        for (id, &(lat, lon)) in coords.iter().enumerate() {
+            assert_eq!((state.ports[&id].latitude, state.ports[&id].longitude), (lat, lon), "port {}", id);
+        }
+        assert_eq!(state.ports[&1].name.as_deref(), Some("North"));
+        assert_eq!(state.ports[&3].max_berths, 2);
+        assert!(!state.ports[&4].has_fuel);
+
+        // a repeated id or a name used twice rejects the whole batch
+        let (state, _) = run_json(r#"{"operations": [
+            {"createports": {"ports": [{"id": 0, "latitude": 0.0, "longitude": 0.0}, {"id": 0, "latitude": 1.0, "longitude": 1.0}]}},
+            {"createports": {"ports": [{"id": 1, "latitude": 0.0, "longitude": 0.0, "name": "Twin"}, {"id": 2, "latitude": 1.0, "longitude": 1.0, "name": "Twin"}]}}
+        ]}"#);
+        assert!(state.ports.is_empty(), "{:?}", state.warnings);
+    }
+
+    #[test]
+    fn short_range_ship_at_an_isolated_port_is_stranded() {
+        // ports 0 and 1 are a degree apart, port 2 is on the far side of the globe
+        let mut ports = ports_at(&[(0.0, 0.0), (0.0, 1.0), (0.0, 180.0)]);
+        let store = HashMap::new();
+        let mut ships = HashMap::new();
+        for (id, port) in [(0, 0), (1, 2)] {
+            let mut ship = ship_at(id, port, &mut ports);
+            ship.max_fuel_capacity = 500.0;
+            ships.insert(id, ship);
+        }
+        assert_eq!(stranded_ships(&ships, &ports, &store, 1.0), vec![1]);
+
+        ships.get_mut(&1).unwrap().max_fuel_capacity = f64::INFINITY;
+        assert!(stranded_ships(&ships, &ports, &store, 1.0).is_empty());
+
+        let lone = ports_at(&[(0.0, 0.0)]);
+        let ships = HashMap::from([(0, Ship { max_fuel_capacity: 1.0, ..Ship::new(0, 0, 100, 1, 1, 1, 1, 1.0) })]);
+        assert!(stranded_ships(&ships, &lone, &store, 1.0).is_empty(), "a single port strands no one");
+    }
+
+    #[test]
+    fn fuel_projection_matches_the_fuel_burned() {
+        let text = VOYAGE.replace("\n    ]}", r#",
+        {"createcontainer": {"id": 8, "weight": 5000, "port_id": 1}},
+        {"unload": {"ship_id": 0, "container_id": 7}},
+        {"load": {"ship_id": 0, "container_id": 8}},
+        {"sail": {"ship_id": 0, "dest_port_id": 0}}
+    ]}"#).replace(r#""total_weight_capacity": 100"#, r#""total_weight_capacity": 10000"#).replace("1000.0", "100000.0");
+        let projection = project_fuel_demand(&parse_input(&text, InputFormat::Json).unwrap());
+        let (state, _) = run_json(&text);
+        let burned = 100_000.0 - state.ships[&0].fuel;
+        assert_eq!(state.ships[&0].current_port, 0, "both legs were sailed");
+        assert!(burned > 0.0);
+        assert!((projection[&0].demand - burned).abs() < 1e-6, "projected {} vs burned {}", projection[&0].demand, burned);
+        assert!(projection[&0].sufficient());
+    }
+
+    #[test]
+    fn bulk_moves_keep_a_consignment_together() {
+        let mut ports = ports_at(&[(0.0, 0.0)]);
+        let mut store = HashMap::new();
+        for id in [1, 2] {
+            store.insert(id, build_container(id, 10, None, ContainerExtras { consignment: Some(9), ..Default::default() }));
+            place_container(id, 0, &mut ports, &mut PortIndex::default());
+        }
+        // room for one member only: a kept consignment loads neither
+        let mut ship = Ship { total_weight_capacity: 15, ..ship_at(0, 0, &mut ports) };
+        assert!(ship.auto_load(LoadStrategy::Greedy, true, &mut ports, &mut store).is_empty());
+        assert_eq!(ship.auto_load(LoadStrategy::Greedy, false, &mut ports, &mut store), vec![1]);
+        assert_eq!(ship.offload_all(true, &mut ports, &mut store), vec![1], "the member aboard rejoins the one ashore");
+
+        ship.total_weight_capacity = 100;
+        assert_eq!(ship.auto_load(LoadStrategy::Greedy, true, &mut ports, &mut store), vec![1, 2]);
+        let mut ships = HashMap::from([(0, ship), (1, ship_at(1, 0, &mut ports))]);
+        ships.get_mut(&1).unwrap().total_weight_capacity = 100;
+        assert_eq!(rebalance(0, true, &mut ports, &mut ships, &mut store), 0, "the consignment stays on one ship");
+        assert_eq!(ships[&0].containers, vec![1, 2]);
+        assert_eq!(ships.get_mut(&0).unwrap().offload_all(true, &mut ports, &mut store), vec![2, 1]);
+        assert_eq!(ports[&0].containers, HashSet::from([1, 2]));
+    }
+
+    #[test]
+    fn partial_consignment_load_is_rejected() {
+        // the ship already carries 10 of its 100, so only one of the two members would fit
+        let text = VOYAGE.replace(r#"{"operations""#, r#"{"keep_consignments": true, "operations""#).replace("\n    ]}", r#",
+        {"createcontainer": {"id": 1, "weight": 50, "port_id": 1, "consignment": 9}},
+        {"createcontainer": {"id": 2, "weight": 50, "port_id": 1, "consignment": 9}},
+        {"loadconsignment": {"ship_id": 0, "consignment": 9}},
+        {"load": {"ship_id": 0, "container_id": 1}},
+        {"unload": {"ship_id": 0, "container_id": 7}},
+        {"loadconsignment": {"ship_id": 0, "consignment": 9}},
+        {"unload": {"ship_id": 0, "container_id": 2}}
+    ]}"#);
+        let (state, _) = run_json(&text);
+        let ops = &state.summary.operations;
+        assert_eq!((ops["LoadConsignment"].total, ops["LoadConsignment"].succeeded), (2, 1));
+        assert_eq!((ops["Load"].total, ops["Load"].succeeded), (2, 1), "a member cannot board on its own");
+        assert_eq!(ops["Unload"].succeeded, 1, "nor leave on its own");
+        assert_eq!(state.ships[&0].containers, vec![1, 2]);
+        assert_eq!(state.ports[&1].containers, HashSet::from([7]));
+    }
+
+    #[test]
+    fn distributed_consignment_needs_every_member_assigned_together() {
+        let text = VOYAGE.replace(r#"{"operations""#, r#"{"keep_consignments": true, "operations""#).replace("\n    ]}", r#",
+        {"createcontainer": {"id": 1, "weight": 10, "port_id": 1, "consignment": 9}},
+        {"createcontainer": {"id": 2, "weight": 10, "port_id": 1, "consignment": 9}},
+        {"loadconsignment": {"ship_id": 0, "consignment": 9}},
+        {"distributecargo": {"ship_id": 0, "assignments": [[1, 0]]}},
+        {"distributecargo": {"ship_id": 0, "assignments": [[1, 0], [2, 0]]}}
+    ]}"#);
+        let (state, _) = run_json(&text);
+        let runs = &state.summary.operations["DistributeCargo"];
+        assert_eq!((runs.total, runs.succeeded), (2, 1));
+        assert!(state.ports[&0].containers.contains(&1) && state.ports[&0].containers.contains(&2));
+    }
+
+    #[test]
+    fn depot_goes_to_the_middle_of_a_line() {
+        let ports = ports_at(&[(0.0, 0.0), (0.0, 1.0), (0.0, 2.0), (0.0, 3.0), (0.0, 5.0)]);
+        let degree_km = 6371.0 * PI / 180.0;
+        let (id, total) = optimal_depot(&ports).unwrap();
+        assert_eq!(id, 2);
+        assert!((total - 7.0 * degree_km).abs() < 1e-6, "total {}", total);
+        assert!(optimal_depot(&HashMap::new()).is_none());
+    }
+
+    #[test]
+    fn geojson_has_a_line_per_sailed_ship() {
+        let text = VOYAGE.replace("\n    ]}", &format!(r#",
+        {{"createport": {{"id": 2, "latitude": 1.0, "longitude": 1.0}}}},
+        {{"sail": {{"ship_id": 0, "dest_port_id": 2}}}},
+        {}
+    ]}}"#, createship_json(1, 0)));
+        let (state, _) = run_json(&text);
+        let geo = routes_geojson(&state.ships, &state.ports);
+        assert_eq!(geo["type"], "FeatureCollection");
+        let features = geo["features"].as_array().unwrap();
+        assert_eq!(features.len(), 1, "ship 1 never sailed");
+        assert_eq!(features[0]["geometry"]["type"], "LineString");
+        assert_eq!(features[0]["properties"]["ship_id"], 0);
+        assert_eq!(features[0]["geometry"]["coordinates"], serde_json::json!([[0.0, 0.0], [1.0, 0.0], [1.0, 1.0]]));
+    }
+
+    #[test]
+    fn capacity_checks_skip_a_scrapped_container() {
+        let mut ports = ports_at(&[(0.0, 0.0)]);
+        let mut store = HashMap::new();
+        let mut ship = ship_at(0, 0, &mut ports);
+        for (id, weight) in [(1, 5000), (2, 100), (3, 200)] {
+            stock(id, weight, None, 0, &mut ports, &mut store);
+        }
+        ship.load(1, &mut ports, &mut store).unwrap();
+        ship.load(2, &mut ports, &mut store).unwrap();
+        // scrapped behind the ship's back: the id stays aboard but the store no longer knows it
+        store.remove(&1);
+        assert_eq!(ship.current_total_weight(&store), 100);
+        assert_eq!(ship.current_heavy_count(&store), 0);
+        ship.load(3, &mut ports, &mut store).unwrap();
+        assert_eq!(ship.current_total_weight(&store), 300);
+        assert_eq!(ship.containers, vec![1, 2, 3]);
+    }
+
+    #[test]
+    fn distance_matrix_is_symmetric_with_a_zero_diagonal() {
+        let mut ports = ports_at(&[(0.0, 0.0), (10.0, 20.0), (-35.0, 150.0)]);
+        // rows follow the id, not the insertion order
+        let far = ports.remove(&2).unwrap();
+        ports.insert(9, Port { id: 9, ..far });
+        let m = distance_matrix(&ports);
+        assert_eq!(m.len(), 3);
+        for (i, row) in m.iter().enumerate() {
+            assert_eq!(row[i], 0.0);
+            for (j, d) in row.iter().enumerate() {
+                assert!((d - m[j][i]).abs() < 1e-9, "{} {}", i, j);
+            }
+        }
+        assert!((m[0][2] - ports[&0].get_distance(&ports[&9])).abs() < 1e-9);
+        assert!(m[0][1] > 0.0 && m[1][2] > 0.0);
+    }
+
+    #[test]
+    fn rebalance_narrows_the_utilization_gap_within_limits() {
+        let mut ports = ports_at(&[(0.0, 0.0)]);
+        let mut store = HashMap::new();
+        let mut full = Ship { total_weight_capacity: 1000, ..ship_at(0, 0, &mut ports) };
+        let mut light = Ship { total_weight_capacity: 2000, max_number_of_all_containers: 3, ..ship_at(1, 0, &mut ports) };
+        for (id, weight) in [(1, 100), (2, 200), (3, 300), (4, 250)] {
+            stock(id, weight, None, 0, &mut ports, &mut store);
+            full.load(id, &mut ports, &mut store).unwrap();
+        }
+        stock(5, 100, None, 0, &mut ports, &mut store);
+        light.load(5, &mut ports, &mut store).unwrap();
+        let mut ships = HashMap::from([(0, full), (1, light)]);
+        let gap = |ships: &HashMap<usize, Ship>, store: &HashMap<usize, ContainerData>| {
+            let util = |s: &Ship| s.current_total_weight(store) as f64 / s.total_weight_capacity as f64;
+            (util(&ships[&0]) - util(&ships[&1])).abs()
+        };
+        let before = gap(&ships, &store);
+
+        assert!(rebalance(0, false, &mut ports, &mut ships, &mut store) > 0);
+        assert!(gap(&ships, &store) < before, "gap {} -> {}", before, gap(&ships, &store));
+        for s in ships.values() {
+            assert!(s.containers.len() <= s.max_number_of_all_containers);
+            assert!(s.current_total_weight(&store) <= s.total_weight_capacity as i64);
+        }
+        assert_eq!(ships[&0].containers.len() + ships[&1].containers.len(), 5);
+        assert!(ports[&0].containers.is_empty(), "nothing is left in the yard");
+    }
+
+    #[test]
+    fn wear_raises_leg_cost_until_maintenance() {
+        let ops = [
+            r#"{"createport": {"id": 0, "latitude": 0.0, "longitude": 0.0}}"#,
+            r#"{"createport": {"id": 1, "latitude": 0.0, "longitude": 30.0}}"#,
+            r#"{"createship": {"id": 0, "port_id": 0, "total_weight_capacity": 100, "max_number_of_all_containers": 5, "max_number_of_heavy_containers": 1, "max_number_of_refrigerated_containers": 1, "max_number_of_liquid_containers": 1, "fuel_consumption_per_km": 1.0, "wear_per_1000km": 0.1}}"#,
+            r#"{"refuel": {"ship_id": 0, "amount": 1000000.0}}"#,
+            r#"{"sail": {"ship_id": 0, "dest_port_id": 1}}"#,
+            r#"{"sail": {"ship_id": 0, "dest_port_id": 0}}"#,
+            r#"{"maintain": {"ship_id": 0}}"#,
+            r#"{"sail": {"ship_id": 0, "dest_port_id": 1}}"#,
+        ];
+        let burned = |n: usize| run_json(&format!(r#"{{"operations": [{}]}}"#, ops[..n].join(","))).0.ships[&0].fuel_burned;
+        let (first, second, third) = (burned(5), burned(6) - burned(5), burned(8) - burned(6));
+        let ports = ports_at(&[(0.0, 0.0), (0.0, 30.0)]);
+        let d = ports[&0].get_distance(&ports[&1]);
+        assert!((first - d).abs() < 1e-6, "a new ship burns the base rate");
+        assert!((second - d * (1.0 + 0.1 * d / 1000.0)).abs() < 1e-6, "second leg {}", second);
+        assert!((third - first).abs() < 1e-6, "maintenance resets the wear");
+    }
+
+    #[test]
+    fn only_ports_without_ships_or_cargo_are_empty() {
+        let text = VOYAGE.replace("\n    ]}", r#",
+        {"createport": {"id": 2, "latitude": 1.0, "longitude": 0.0}},
+        {"createport": {"id": 3, "latitude": 1.0, "longitude": 1.0}},
+        {"createcontainer": {"id": 8, "weight": 10, "port_id": 3}}
+    ]}"#);
+        let (state, _) = run_json(&text);
+        // port 0 was left behind with its yard loaded away, port 1 has the ship, port 3 a container
+        assert_eq!(empty_ports(&state.ports), vec![0, 2]);
+    }
+
+    #[test]
+    fn toml_scenario_matches_its_json_twin() {
+        let toml = r#"
+            [[operations]]
+            createport = { id = 0, latitude = 0.0, longitude = 0.0 }
+            [[operations]]
+            createport = { id = 1, latitude = 0.0, longitude = 1.0 }
+            [[operations]]
+            createship = { id = 0, port_id = 0, total_weight_capacity = 100, max_number_of_all_containers = 5, max_number_of_heavy_containers = 1, max_number_of_refrigerated_containers = 1, max_number_of_liquid_containers = 1, fuel_consumption_per_km = 1.0 }
+            [[operations]]
+            createcontainer = { id = 7, weight = 10, port_id = 0 }
+            [[operations]]
+            refuel = { ship_id = 0, amount = 1000.0 }
+            [[operations]]
+            load = { ship_id = 0, container_id = 7 }
+            [[operations]]
+            sail = { ship_id = 0, dest_port_id = 1 }
+            [[operations]]
+            countcontainers = {}
+        "#;
+        let json = VOYAGE.replace("\n    ]}", ",\n        \"countcontainers\"\n    ]}");
+        let (toml_state, toml_out) = run_as(toml, InputFormat::Toml);
+        let (json_state, json_out) = run_json(&json);
+        assert_eq!(toml_out, json_out);
+        assert_eq!(toml_state.summary.operations["CountContainers"].succeeded, 1);
+        assert_eq!(json_state.summary.operations.len(), toml_state.summary.operations.len());
+        assert_eq!(InputFormat::for_path("scenario.toml", false), InputFormat::Toml);
+
+        // a slip after a unit operation is explained like its JSON counterpart
+        let broken = format!("{}\n            [[operations]]\n            createport = {{ id = 2, latitude = 1.0 }}\n", toml);
+        assert_eq!(parse_input(&broken, InputFormat::Toml).err().as_deref(), Some("invalid TOML: CreatePort at index 8 missing 'longitude'"));
+    }
+
+    #[test]
+    fn closest_reachable_skips_ports_the_fuel_cannot_cover() {
+        let mut ports = ports_at(&[(0.0, 0.0), (0.0, 1.0), (0.0, 2.0), (0.0, 10.0)]);
+        // a storm makes the nearest leg cost five times the distance
+        ports.get_mut(&0).unwrap().weather.insert(1, 5.0);
+        let store = HashMap::new();
+        let mut ship = ship_at(0, 0, &mut ports);
+        ship.fuel = 300.0;
+        let (id, d) = closest_reachable(&ship, &ports, &store, 1.0).unwrap();
+        assert_eq!(id, 2);
+        assert!((d - ports[&0].get_distance(&ports[&2])).abs() < 1e-9);
+
+        ship.fuel = 100.0;
+        assert_eq!(closest_reachable(&ship, &ports, &store, 1.0), None, "stranded on what is left");
+    }
+
+    #[test]
+    fn nested_and_flat_limits_build_the_same_ship() {
+        let nested = VOYAGE.replace(
+            r#""max_number_of_all_containers": 5, "max_number_of_heavy_containers": 1, "max_number_of_refrigerated_containers": 1, "max_number_of_liquid_containers": 1"#,
+            r#""limits": {"all": 5, "heavy": 1, "refrigerated": 1, "liquid": 1}"#,
+        );
+        assert_ne!(nested, VOYAGE);
+        let ((nested_state, nested_out), (flat_state, flat_out)) = (run_json(&nested), run_json(VOYAGE));
+        assert_eq!(nested_out, flat_out);
+        let limits = |s: &Ship| (s.max_number_of_all_containers, s.max_number_of_heavy_containers, s.max_number_of_refrigerated_containers, s.max_number_of_liquid_containers);
+        assert_eq!(limits(&nested_state.ships[&0]), (5, 1, 1, 1));
+        assert_eq!(limits(&nested_state.ships[&0]), limits(&flat_state.ships[&0]));
+
+        // half a limits object fits neither form, and the error says what would
+        let partial = nested.replace(r#", "heavy": 1, "refrigerated": 1, "liquid": 1"#, "");
+        let err = parse_input(&partial, InputFormat::Json).err().unwrap();
+        assert!(err.starts_with("invalid JSON: CreateShip at index 2: ship limits:"), "{}", err);
+    }
+
+    #[test]
+    fn port_that_ships_out_more_than_it_takes_in_has_positive_net_flow() {
+        let text = VOYAGE.replace("\n    ]}", r#",
+        {"createcontainer": {"id": 8, "weight": 10, "port_id": 1}},
+        {"unload": {"ship_id": 0, "container_id": 7}},
+        {"load": {"ship_id": 0, "container_id": 8}},
+        {"createcontainer": {"id": 9, "weight": 10, "port_id": 1}},
+        {"load": {"ship_id": 0, "container_id": 9}},
+        {"sail": {"ship_id": 0, "dest_port_id": 0}},
+        {"unload": {"ship_id": 0, "container_id": 8}}
+    ]}"#);
+        let (state, _) = run_json(&text);
+        // port 1 took in container 7 and sent out 8 and 9; port 0 sent 7 and got 8 back
+        assert_eq!((state.ports[&0].net_flow(), state.ports[&1].net_flow()), (0, 1));
+        assert_eq!((state.ports[&1].departed, state.ports[&1].arrived), (2, 1));
+    }
+
+    #[test]
+    fn zero_consumption_ship_is_rejected() {
+        let text = VOYAGE.replace(r#""fuel_consumption_per_km": 1.0"#, r#""fuel_consumption_per_km": 0.0"#);
+        let mut input = parse_input(&text, InputFormat::Json).unwrap();
+        let err = run(&mut input, true, false).err().unwrap();
+        assert!(err.contains("Cannot create ship 0: fuel_consumption_per_km must be positive, got 0"), "{}", err);
+
+        let (state, _) = run_json(&text);
+        assert!(state.ships.is_empty());
+        assert_eq!(state.summary.operations["CreateShip"].succeeded, 0);
+
+        // upserting an existing ship to a zero rate is refused too, and the ship keeps its old rate
+        let text = VOYAGE.replace("\n    ]}", r#",
+        {"createship": {"id": 0, "port_id": 0, "total_weight_capacity": 100, "max_number_of_all_containers": 5, "max_number_of_heavy_containers": 1, "max_number_of_refrigerated_containers": 1, "max_number_of_liquid_containers": 1, "fuel_consumption_per_km": 0.0, "upsert": true}}
+    ]}"#);
+        let (state, _) = run_json(&text);
+        assert_eq!(state.ships[&0].fuel_consumption_per_km, 1.0);
+    }
+
+    #[test]
+    fn checkpoint_diff_reports_only_the_sailed_ship() {
+        let text = VOYAGE
+            .replace(r#"        {"sail""#, &format!(r#"        {},
+        {{"checkpoint": {{"label": "before"}}}},
+        {{"sail""#, createship_json(1, 0)))
+            .replace("\n    ]}", ",\n        {\"checkpoint\": {\"label\": \"after\"}}\n    ]}");
+        let (state, _) = run_json(&text);
+        let diff = diff_snapshots(&state.checkpoints["before"], &state.checkpoints["after"]);
+        assert_eq!(diff.ships_moved, vec![(0, 0, 1)]);
+        let cost = state.ports[&0].get_distance(&state.ports[&1]) + state.container_store[&7].total_consumption();
+        assert_eq!(diff.fuel_deltas.len(), 1);
+        assert_eq!(diff.fuel_deltas[0].0, 0);
+        assert!((diff.fuel_deltas[0].1 + cost).abs() < 1e-9, "delta {:?}", diff.fuel_deltas);
+        assert!(diff.ships_added.is_empty() && diff.ships_removed.is_empty());
+        assert!(diff.containers_relocated.is_empty(), "container 7 stayed aboard");
+    }
+
+    #[test]
+    fn voyage_cost_allocations_add_up_to_the_leg() {
+        let mut ports = ports_at(&[(0.0, 0.0), (3.0, 4.0)]);
+        let mut store = HashMap::new();
+        let mut ship = ship_at(0, 0, &mut ports);
+        for (id, weight, special) in [(1, 100, None), (2, 4000, None), (3, 5000, Some("R")), (4, 3500, Some("L"))] {
+            stock(id, weight, special, 0, &mut ports, &mut store);
+            ship.load(id, &mut ports, &mut store).unwrap();
+        }
+        let leg_km = ports[&0].get_distance(&ports[&1]);
+        // weather and density are already in the leg's fuel
+        let leg_fuel = ship.voyage_fuel_cost(&ports[&0], &ports[&1], &store, 1.0) * 1.3;
+        for split in [BaseFuelSplit::Even, BaseFuelSplit::ByWeight] {
+            let shares = allocate_voyage_cost(&ship, leg_fuel, leg_km, &store, split);
+            assert_eq!(shares.len(), 4);
+            let total: f64 = shares.values().sum();
+            assert!((total - leg_fuel).abs() < 1e-6, "{:?}: {} vs {}", split, total, leg_fuel);
+        }
+        assert!(allocate_voyage_cost(&ship_at(1, 0, &mut ports), leg_fuel, leg_km, &store, BaseFuelSplit::Even).is_empty());
+
+        // a real Sail charges what the ship burned to the container aboard
+        let (state, _) = run_json(VOYAGE);
+        let charged: f64 = state.summary.container_fuel.values().sum();
+        assert_eq!(state.summary.container_fuel.keys().copied().collect::<Vec<_>>(), vec![7]);
+        assert!((charged - state.ships[&0].fuel_burned).abs() < 1e-6, "{} vs {}", charged, state.ships[&0].fuel_burned);
+    }
+
+    #[test]
+    fn ship_at_a_missing_port_is_rejected_without_a_later_panic() {
+        let text = VOYAGE.replace("\n    ]}", &format!(r#",
+        {},
+        {{"refuel": {{"ship_id": 3, "amount": 100.0}}}},
+        {{"load": {{"ship_id": 3, "container_id": 7}}}},
+        {{"sail": {{"ship_id": 3, "dest_port_id": 0}}}}
+    ]}}"#, createship_json(3, 5)));
+        let mut input = parse_input(&text, InputFormat::Json).unwrap();
+        assert!(!project_fuel_demand(&input).contains_key(&3));
+        let err = run(&mut input, true, false).err().unwrap();
+        assert!(err.contains("Cannot create ship 3: unknown port 5"), "{}", err);
+
+        let (state, out) = run_json(&text);
+        assert!(!state.ships.contains_key(&3));
+        for op in ["CreateShip", "Refuel", "Load", "Sail"] {
+            assert_eq!(state.summary.operations[op].total - state.summary.operations[op].succeeded, 1, "{}", op);
+        }
+        assert_eq!(out["Port 1"]["ships"]["ship_0"]["basic_container"], serde_json::json!([7]), "ship 0 carries on as before");
+    }
 }